@@ -0,0 +1,19 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id          INTEGER PRIMARY KEY NOT NULL,
+    token_hash  VARCHAR(64) NOT NULL UNIQUE,
+    username    VARCHAR(250) NOT NULL,
+    family_id   VARCHAR(64) NOT NULL,
+    expires_at  INTEGER NOT NULL,
+    revoked_at  INTEGER,
+    replaced_by INTEGER,
+    created_at  DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (replaced_by) REFERENCES refresh_tokens(id)
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_idx ON refresh_tokens (family_id);
+
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti         VARCHAR(64) PRIMARY KEY NOT NULL,
+    expires_at  INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'reader';
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE authors ADD COLUMN created_by INTEGER REFERENCES users(id);
+
+ALTER TABLE posts ADD COLUMN created_by INTEGER REFERENCES users(id);
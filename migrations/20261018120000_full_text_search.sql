@@ -0,0 +1,47 @@
+-- Add migration script here
+CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(
+    title,
+    content,
+    content = 'posts',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS authors_fts USING fts5(
+    name,
+    surname,
+    content = 'authors',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- Keep the indexes in sync with their content tables
+CREATE TRIGGER IF NOT EXISTS posts_fts_insert AFTER INSERT ON posts BEGIN
+    INSERT INTO posts_fts (rowid, title, content) VALUES (new.id, new.title, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS posts_fts_delete AFTER DELETE ON posts BEGIN
+    INSERT INTO posts_fts (posts_fts, rowid, title, content) VALUES ('delete', old.id, old.title, old.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS posts_fts_update AFTER UPDATE OF title, content ON posts BEGIN
+    INSERT INTO posts_fts (posts_fts, rowid, title, content) VALUES ('delete', old.id, old.title, old.content);
+    INSERT INTO posts_fts (rowid, title, content) VALUES (new.id, new.title, new.content);
+END;
+
+CREATE TRIGGER IF NOT EXISTS authors_fts_insert AFTER INSERT ON authors BEGIN
+    INSERT INTO authors_fts (rowid, name, surname) VALUES (new.id, new.name, new.surname);
+END;
+
+CREATE TRIGGER IF NOT EXISTS authors_fts_delete AFTER DELETE ON authors BEGIN
+    INSERT INTO authors_fts (authors_fts, rowid, name, surname) VALUES ('delete', old.id, old.name, old.surname);
+END;
+
+CREATE TRIGGER IF NOT EXISTS authors_fts_update AFTER UPDATE OF name, surname ON authors BEGIN
+    INSERT INTO authors_fts (authors_fts, rowid, name, surname) VALUES ('delete', old.id, old.name, old.surname);
+    INSERT INTO authors_fts (rowid, name, surname) VALUES (new.id, new.name, new.surname);
+END;
+
+-- Index rows that existed before this migration
+INSERT INTO posts_fts (posts_fts) VALUES ('rebuild');
+INSERT INTO authors_fts (authors_fts) VALUES ('rebuild');
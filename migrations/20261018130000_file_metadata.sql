@@ -0,0 +1,24 @@
+-- Add migration script here
+ALTER TABLE files ADD COLUMN filename VARCHAR(255);
+ALTER TABLE files ADD COLUMN mime_type VARCHAR(100) NOT NULL DEFAULT 'application/octet-stream';
+ALTER TABLE files ADD COLUMN size INTEGER NOT NULL DEFAULT 0;
+-- Hex SHA-256 of the content. Existing rows are hashed by the server on startup
+ALTER TABLE files ADD COLUMN sha256 CHAR(64);
+ALTER TABLE files ADD COLUMN uploaded_at DATETIME;
+
+CREATE INDEX IF NOT EXISTS files_post_id ON files (post_id);
+
+-- Backfill what SQL can work out for files stored before this migration.
+-- The original filenames were never stored, so they stay NULL
+UPDATE files SET
+    size = COALESCE(length(file), 0),
+    uploaded_at = COALESCE((SELECT created_at FROM posts WHERE posts.id = files.post_id), CURRENT_TIMESTAMP),
+    mime_type = CASE
+        WHEN hex(substr(file, 1, 4)) = '25504446' THEN 'application/pdf'
+        WHEN hex(substr(file, 1, 4)) = '54455854' THEN 'text/plain'
+        WHEN hex(substr(file, 1, 4)) = '504B0304' THEN 'application/vnd.openxmlformats-officedocument.wordprocessingml.document'
+        WHEN hex(substr(file, 1, 4)) = 'D0CF11E0' THEN 'application/msword'
+        WHEN hex(substr(file, 1, 8)) = '89504E470D0A1A0A' THEN 'image/png'
+        WHEN hex(substr(file, 1, 3)) = 'FFD8FF' THEN 'image/jpeg'
+        ELSE 'application/octet-stream'
+    END;
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Which BlobStore holds a row's bytes: 'sqlite' keeps them in the row itself,
+-- 'filesystem' leaves the blob column NULL and stores them by SHA-256 on disk
+ALTER TABLE files ADD COLUMN storage VARCHAR(20) NOT NULL DEFAULT 'sqlite';
+
+ALTER TABLE authors ADD COLUMN photo_storage VARCHAR(20) NOT NULL DEFAULT 'sqlite';
+-- Existing photos are hashed by the server on startup
+ALTER TABLE authors ADD COLUMN photo_sha256 CHAR(64);
+
+CREATE INDEX IF NOT EXISTS files_sha256 ON files (sha256);
+CREATE INDEX IF NOT EXISTS authors_photo_sha256 ON authors (photo_sha256);
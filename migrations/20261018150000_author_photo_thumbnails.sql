@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Photos used to be saved through a lossy UTF-8 conversion, which stored them
+-- as TEXT and replaced every invalid byte. PNG and JPEG data never survives
+-- that, so these photos can't be decoded any more and are dropped
+UPDATE authors SET photo = NULL, photo_sha256 = NULL WHERE typeof(photo) = 'text';
+
+-- Resized variants of author photos, keyed by the photo they were made from.
+-- A width or height of 0 means the request left that side unconstrained
+CREATE TABLE IF NOT EXISTS author_photo_thumbnails (
+    photo_sha256 CHAR(64) NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (photo_sha256, width, height)
+);
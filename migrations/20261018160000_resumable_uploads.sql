@@ -0,0 +1,26 @@
+-- Add migration script here
+-- tus uploads in progress. A finished upload waits here until a post handler
+-- attaches it, abandoned ones are deleted once expires_at has passed
+CREATE TABLE IF NOT EXISTS uploads (
+    id CHAR(32) PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL,
+    filename VARCHAR(255),
+    -- Upload-Metadata as the client sent it, echoed back on HEAD
+    metadata TEXT,
+    length INTEGER NOT NULL,
+    received INTEGER NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS uploads_expires_at ON uploads (expires_at);
+
+-- The bytes of an upload, one row per piece received, keyed by where it starts
+CREATE TABLE IF NOT EXISTS upload_chunks (
+    upload_id CHAR(32) NOT NULL,
+    start INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (upload_id, start),
+    FOREIGN KEY (upload_id) REFERENCES uploads(id) ON DELETE CASCADE
+);
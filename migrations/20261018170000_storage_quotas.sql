@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Who uploaded a file, whose storage quota it counts against
+ALTER TABLE files ADD COLUMN uploaded_by INTEGER REFERENCES users(id);
+
+-- Files from before this migration count against whoever created the post
+UPDATE files SET uploaded_by = (SELECT created_by FROM posts WHERE posts.id = files.post_id);
+
+CREATE INDEX IF NOT EXISTS files_uploaded_by ON files (uploaded_by);
+
+-- Bytes this user may store, NULL for the limit of their role
+ALTER TABLE users ADD COLUMN storage_quota INTEGER;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Outcome of the malware scan: 'unscanned', 'clean' or 'quarantined'.
+-- Quarantined files are kept but never downloaded
+ALTER TABLE files ADD COLUMN scan_status VARCHAR(20) NOT NULL DEFAULT 'unscanned';
+
+-- The signature the scanner matched, for quarantined files
+ALTER TABLE files ADD COLUMN scan_result TEXT;
+
+CREATE INDEX IF NOT EXISTS files_scan_status ON files (scan_status);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Every change event sent to WebSocket and event stream clients, so clients
+-- that reconnect can be sent what they missed. seq is the event's id and
+-- never goes back, not even once old rows are pruned
+CREATE TABLE IF NOT EXISTS events (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- The event as an admin receives it, its seq is this row's
+    payload TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS events_created_at ON events (created_at);
@@ -1,80 +1,341 @@
 const BEARER: &str = "Bearer ";
-const JWT_SECRET: &[u8] = b"secret";
+use std::sync::Arc;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm, EncodingKey, Header, encode};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, SqlitePool};
 use chrono::Utc; // Import Utc from the chrono crate
+use uuid::Uuid;
+use crate::models::user::Role;
+use crate::config::{AuthConfig, Config};
 use warp::{
     filters::header::headers_cloned,
     http::header::{HeaderMap, HeaderValue, AUTHORIZATION},
     Filter, Rejection,
-    
+
 };
-use warp::reject::Reject;
+use crate::errors::ApiError;
 
-#[derive(Debug)]
-pub enum MyError {
-    JWTTokenError,
-    JWTTokenCreationError,
-    NoAuthHeaderError,
-    InvalidAuthHeaderError,
-    // Add more custom error variants as needed
-}
-impl Reject for MyError {}
-#[derive(Debug, Deserialize, Serialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+    // Unique token id, used to revoke a single access token
+    pub jti: String,
+    // Refresh token family the access token was issued with
+    pub sid: String,
+    #[serde(default)]
+    pub role: Role,
 }
 
-pub fn create_jwt(username: &String) -> Result<String, MyError> {
+pub fn create_jwt(auth: &AuthConfig, username: &String, role: Role, session_id: &str) -> Result<String, ApiError> {
     let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::minutes(20))  // 5 MINUTE LIFESPAN
+        .checked_add_signed(chrono::Duration::minutes(auth.access_token_minutes))
         .expect("valid timestamp")
         .timestamp();
 
     let claims = Claims {
         sub: username.to_string(),
         exp: expiration as usize,
+        jti: Uuid::new_v4().to_simple().to_string(),
+        sid: session_id.to_string(),
+        role,
     };
     let header = Header::new(Algorithm::HS512);
-    encode(&header, &claims, &EncodingKey::from_secret(JWT_SECRET))
-        .map_err(|_| MyError::JWTTokenCreationError)
+    encode(&header, &claims, &EncodingKey::from_secret(auth.jwt_secret.as_bytes()))
+        .map_err(|_| ApiError::Internal("Failed to create token".to_string()))
 }
 
 
 
-fn jwt_from_header(headers: &HeaderMap<HeaderValue>) -> Result<String, MyError> {
+pub fn jwt_from_header(headers: &HeaderMap<HeaderValue>) -> Result<String, ApiError> {
     let header = match headers.get(AUTHORIZATION) {
         Some(v) => v,
-        None => return Err(MyError::NoAuthHeaderError),
+        None => return Err(ApiError::Unauthorized("Missing Authorization header".to_string())),
     };
     let auth_header = match std::str::from_utf8(header.as_bytes()) {
         Ok(v) => v,
-        Err(_) => return Err(MyError::NoAuthHeaderError),
+        Err(_) => return Err(ApiError::Unauthorized("Invalid Authorization header".to_string())),
     };
     if !auth_header.starts_with(BEARER) {
-        return Err(MyError::InvalidAuthHeaderError);
+        return Err(ApiError::Unauthorized("Authorization header must use the Bearer scheme".to_string()));
     }
     Ok(auth_header.trim_start_matches(BEARER).to_owned())
 }
 
-async fn authorize(headers: HeaderMap<HeaderValue>) -> Result<String, Rejection> {
-    match jwt_from_header(&headers) {
-        Ok(jwt) => {
-            let decoded = decode::<Claims>(
-                &jwt,
-                &DecodingKey::from_secret(JWT_SECRET),
-                &Validation::new(Algorithm::HS512),
-            )
-            .map_err(|_| warp::reject::custom(MyError::JWTTokenError))?;
+async fn is_revoked(db: &SqlitePool, jti: &str) -> Result<bool, ApiError> {
+    sqlx::query("SELECT jti FROM revoked_tokens WHERE jti = ?")
+        .bind(jti)
+        .fetch_optional(db)
+        .await
+        .map(|row| row.is_some())
+        .map_err(ApiError::from)
+}
 
-            Ok(decoded.claims.sub)
-        }
-        Err(e) => Err(warp::reject::custom(e)),
+//CHECK A BEARER TOKEN'S SIGNATURE, EXPIRY AND REVOCATION
+//
+// Shared by the HTTP filters below, the WebSocket handshake and the event stream
+pub async fn validate_jwt(db: &SqlitePool, config: &Config, jwt: &str) -> Result<Claims, ApiError> {
+    let decoded = decode::<Claims>(
+        jwt,
+        &DecodingKey::from_secret(config.auth.jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS512),
+    )
+    .map_err(|_| ApiError::Unauthorized("Invalid or expired token".to_string()))?;
+
+    if is_revoked(db, &decoded.claims.jti).await? {
+        return Err(ApiError::Unauthorized("Token has been revoked".to_string()));
     }
+
+    Ok(decoded.claims)
+}
+
+async fn authorize(headers: HeaderMap<HeaderValue>, db: SqlitePool, config: Arc<Config>) -> Result<Claims, Rejection> {
+    let jwt = jwt_from_header(&headers)?;
+    Ok(validate_jwt(&db, &config, &jwt).await?)
 }
-pub fn with_auth() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+
+pub fn with_claims(db: SqlitePool, config: Arc<Config>) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
     headers_cloned()
         .map(move |headers: HeaderMap<HeaderValue>| headers)
+        .and(warp::any().map(move || db.clone()))
+        .and(warp::any().map(move || config.clone()))
         .and_then(authorize)
-}
\ No newline at end of file
+}
+
+pub fn with_auth(db: SqlitePool, config: Arc<Config>) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    with_claims(db, config).map(|claims: Claims| claims.sub)
+}
+
+//LIKE with_auth, BUT ALSO REQUIRES AT LEAST THE GIVEN ROLE
+pub fn with_role(db: SqlitePool, config: Arc<Config>, required: Role) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    with_claims(db, config).and_then(move |claims: Claims| async move {
+        if claims.role >= required {
+            Ok(claims.sub)
+        } else {
+            Err(warp::reject::custom(ApiError::Forbidden(format!("Requires the {} role", required.as_str()))))
+        }
+    })
+}
+
+pub async fn get_user_role(db: &SqlitePool, username: &str) -> Result<Role, ApiError> {
+    let role: (String,) = sqlx::query_as("SELECT role FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(db)
+        .await?;
+
+    Ok(role.0.parse().unwrap_or_default())
+}
+
+//GIVE A USER A NEW ROLE, TAKES EFFECT WITH THEIR NEXT TOKEN
+pub async fn set_user_role(db: &SqlitePool, username: &str, role: Role) -> Result<(), ApiError> {
+    let result = sqlx::query("UPDATE users SET role = ? WHERE username = ?")
+        .bind(role.as_str())
+        .bind(username)
+        .execute(db)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("User not found".to_string()));
+    }
+    Ok(())
+}
+
+// Whether anyone can hand out roles yet
+pub async fn has_admin(db: &SqlitePool) -> Result<bool, ApiError> {
+    let admin: Option<(i64,)> = sqlx::query_as("SELECT 1 FROM users WHERE role = ? LIMIT 1")
+        .bind(Role::Admin.as_str())
+        .fetch_optional(db)
+        .await?;
+    Ok(admin.is_some())
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct CurrentUser {
+    pub id: i64,
+    pub role: String,
+}
+
+impl CurrentUser {
+    pub fn role(&self) -> Role {
+        self.role.parse().unwrap_or_default()
+    }
+
+    // Owners can change their own records, admins can change anything
+    pub fn can_modify(&self, created_by: Option<i64>) -> bool {
+        self.role() == Role::Admin || created_by == Some(self.id)
+    }
+}
+
+//LOOK UP THE ACCOUNT BEHIND AN AUTHENTICATED SUBJECT
+pub async fn get_current_user(db: &SqlitePool, username: &str) -> Result<CurrentUser, ApiError> {
+    sqlx::query_as("SELECT id, role FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("Account no longer exists".to_string()))
+}
+
+//REVOKE A SINGLE ACCESS TOKEN UNTIL IT WOULD HAVE EXPIRED ANYWAY
+pub async fn revoke_access_token(db: &SqlitePool, claims: &Claims) -> Result<(), ApiError> {
+    let now = Utc::now().timestamp();
+
+    // Entries are only needed while the token itself is still valid
+    sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < ?")
+        .bind(now)
+        .execute(db)
+        .await?;
+
+    sqlx::query("INSERT OR IGNORE INTO revoked_tokens (jti, expires_at) VALUES (?, ?)")
+        .bind(&claims.jti)
+        .bind(claims.exp as i64)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+
+
+#[derive(Debug, FromRow)]
+struct RefreshTokenRow {
+    id: i64,
+    username: String,
+    family_id: String,
+    expires_at: i64,
+    revoked_at: Option<i64>,
+    replaced_by: Option<i64>,
+}
+
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+async fn insert_refresh_token(db: &SqlitePool, auth: &AuthConfig, username: &str, family_id: &str) -> Result<(i64, String), ApiError> {
+    let mut raw = [0u8; 32];
+    OsRng.fill_bytes(&mut raw);
+    let token = hex::encode(raw);
+
+    let expires_at = Utc::now()
+        .checked_add_signed(chrono::Duration::days(auth.refresh_token_days))
+        .expect("valid timestamp")
+        .timestamp();
+
+    let query = "
+        INSERT INTO refresh_tokens (token_hash, username, family_id, expires_at)
+        VALUES (?, ?, ?, ?)
+    ";
+
+    // Only the hash is stored, so a leaked database can't be used to mint sessions
+    let result = sqlx::query(query)
+        .bind(hash_refresh_token(&token))
+        .bind(username)
+        .bind(family_id)
+        .bind(expires_at)
+        .execute(db)
+        .await?;
+
+    Ok((result.last_insert_rowid(), token))
+}
+
+//START A NEW SESSION: ACCESS TOKEN + FIRST REFRESH TOKEN OF A NEW FAMILY
+pub async fn issue_tokens(db: &SqlitePool, auth: &AuthConfig, username: &String, role: Role) -> Result<IssuedTokens, ApiError> {
+    let family_id = Uuid::new_v4().to_simple().to_string();
+    let (_, refresh_token) = insert_refresh_token(db, auth, username, &family_id).await?;
+    let access_token = create_jwt(auth, username, role, &family_id)?;
+
+    Ok(IssuedTokens { access_token, refresh_token })
+}
+
+//EXCHANGE A REFRESH TOKEN FOR A NEW PAIR. EVERY REFRESH TOKEN CAN BE USED ONCE
+pub async fn rotate_refresh_token(db: &SqlitePool, auth: &AuthConfig, refresh_token: &str) -> Result<IssuedTokens, ApiError> {
+    let query = "
+        SELECT id, username, family_id, expires_at, revoked_at, replaced_by
+        FROM refresh_tokens
+        WHERE token_hash = ?
+    ";
+
+    let stored: RefreshTokenRow = sqlx::query_as(query)
+        .bind(hash_refresh_token(refresh_token))
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| ApiError::Unauthorized("Invalid or expired refresh token".to_string()))?;
+
+    let now = Utc::now().timestamp();
+
+    if stored.replaced_by.is_some() {
+        // An already rotated token came back: assume it was stolen and end the whole session
+        revoke_session(db, &stored.family_id).await?;
+        return Err(ApiError::Unauthorized("Refresh token was already used, the session has been revoked".to_string()));
+    }
+    if stored.revoked_at.is_some() || stored.expires_at < now {
+        return Err(ApiError::Unauthorized("Invalid or expired refresh token".to_string()));
+    }
+
+    let (new_id, new_token) = insert_refresh_token(db, auth, &stored.username, &stored.family_id).await?;
+
+    let query = "
+        UPDATE refresh_tokens
+        SET revoked_at = ?,
+        replaced_by = ?
+        WHERE id = ? AND replaced_by IS NULL AND revoked_at IS NULL
+    ";
+
+    let rotated = sqlx::query(query)
+        .bind(now)
+        .bind(new_id)
+        .bind(stored.id)
+        .execute(db)
+        .await?;
+
+    if rotated.rows_affected() != 1 {
+        // Lost a race against a concurrent refresh with the same token
+        revoke_session(db, &stored.family_id).await?;
+        return Err(ApiError::Unauthorized("Refresh token was already used, the session has been revoked".to_string()));
+    }
+
+    // Pick up role changes made since the session started
+    let role = get_user_role(db, &stored.username).await?;
+    let access_token = create_jwt(auth, &stored.username, role, &stored.family_id)?;
+
+    Ok(IssuedTokens { access_token, refresh_token: new_token })
+}
+
+//WHETHER A TOKEN HOLDING A CONNECTION OPEN WAS REVOKED SINCE IT WAS CHECKED
+//
+// Either on its own, or along with its session: logging out and reusing a
+// refresh token revoke every refresh token of the family
+pub async fn is_token_revoked(db: &SqlitePool, claims: &Claims) -> Result<bool, ApiError> {
+    if is_revoked(db, &claims.jti).await? {
+        return Ok(true);
+    }
+
+    let (active,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM refresh_tokens WHERE family_id = ? AND revoked_at IS NULL")
+        .bind(&claims.sid)
+        .fetch_one(db)
+        .await?;
+    Ok(active == 0)
+}
+
+//REVOKE EVERY REFRESH TOKEN OF A SESSION
+pub async fn revoke_session(db: &SqlitePool, family_id: &str) -> Result<(), ApiError> {
+    let query = "
+        UPDATE refresh_tokens
+        SET revoked_at = ?
+        WHERE family_id = ? AND revoked_at IS NULL
+    ";
+
+    sqlx::query(query)
+        .bind(Utc::now().timestamp())
+        .bind(family_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
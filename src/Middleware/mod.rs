@@ -1,2 +1,5 @@
 pub mod auth;
-pub mod mime_check;
\ No newline at end of file
+pub mod mime_check;
+pub mod password;
+pub mod photo;
+pub mod sniff;
@@ -0,0 +1,239 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use sha2::{Digest, Sha256};
+
+use crate::errors::ApiError;
+
+#[derive(Debug)]
+pub struct PasswordError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    Valid { needs_rehash: bool },
+}
+
+// A way of turning a password into the string kept in `users.password`.
+pub trait PasswordScheme: Send + Sync {
+    // Whether a stored hash was produced by this scheme.
+    fn recognizes(&self, stored: &str) -> bool;
+    fn hash(&self, password: &str) -> Result<String, PasswordError>;
+    fn verify(&self, password: &str, stored: &str) -> bool;
+    // Whether a hash produced by this scheme is out of date (e.g. weaker parameters).
+    fn is_outdated(&self, _stored: &str) -> bool {
+        false
+    }
+}
+
+//ARGON2ID, STORED AS A PHC STRING ($argon2id$v=19$m=...,t=...,p=...$salt$hash)
+#[derive(Default)]
+pub struct Argon2idScheme {
+    params: Params,
+}
+
+impl Argon2idScheme {
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordScheme for Argon2idScheme {
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.starts_with("$argon2")
+    }
+
+    fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| PasswordError)
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> bool {
+        match PasswordHash::new(stored) {
+            // Argon2 compares the digests in constant time
+            Ok(parsed) => self.argon2().verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn is_outdated(&self, stored: &str) -> bool {
+        let parsed = match PasswordHash::new(stored) {
+            Ok(parsed) => parsed,
+            Err(_) => return true,
+        };
+        if parsed.algorithm != Algorithm::Argon2id.ident() || parsed.version != Some(Version::V0x13.into()) {
+            return true;
+        }
+        match Params::try_from(&parsed) {
+            Ok(params) => {
+                params.m_cost() != self.params.m_cost()
+                    || params.t_cost() != self.params.t_cost()
+                    || params.p_cost() != self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+//LEGACY UNSALTED SHA-256, STORED AS 64 HEX CHARACTERS. VERIFY ONLY
+pub struct LegacySha256Scheme;
+
+impl PasswordScheme for LegacySha256Scheme {
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.len() == 64 && stored.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    fn hash(&self, _password: &str) -> Result<String, PasswordError> {
+        // New passwords are never stored with this scheme
+        Err(PasswordError)
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> bool {
+        let provided = Sha256::digest(password.as_bytes());
+        match hex::decode(stored) {
+            Ok(stored) => constant_time_eq(&provided, &stored),
+            Err(_) => false,
+        }
+    }
+
+    fn is_outdated(&self, _stored: &str) -> bool {
+        true
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Hashes new passwords with the current scheme and verifies against any known scheme.
+pub struct Passwords {
+    current: Box<dyn PasswordScheme>,
+    legacy: Vec<Box<dyn PasswordScheme>>,
+}
+
+impl Default for Passwords {
+    fn default() -> Self {
+        Passwords::new(Box::new(Argon2idScheme::default()))
+            .with_legacy(Box::new(LegacySha256Scheme))
+    }
+}
+
+impl Passwords {
+    pub fn new(current: Box<dyn PasswordScheme>) -> Self {
+        Passwords { current, legacy: Vec::new() }
+    }
+
+    pub fn with_legacy(mut self, scheme: Box<dyn PasswordScheme>) -> Self {
+        self.legacy.push(scheme);
+        self
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        self.current.hash(password)
+    }
+
+    pub fn verify(&self, password: &str, stored: &str) -> Verification {
+        if self.current.recognizes(stored) {
+            return match self.current.verify(password, stored) {
+                true => Verification::Valid { needs_rehash: self.current.is_outdated(stored) },
+                false => Verification::Invalid,
+            };
+        }
+
+        match self.legacy.iter().find(|scheme| scheme.recognizes(stored)) {
+            Some(scheme) if scheme.verify(password, stored) => Verification::Valid { needs_rehash: true },
+            _ => Verification::Invalid,
+        }
+    }
+}
+
+//HASH A NEW PASSWORD WITH THE CURRENT SCHEME, ON THE BLOCKING POOL
+//
+// Argon2 takes tens of milliseconds of CPU, which would stall every other
+// request sharing the async worker
+pub async fn hash_password(password: String) -> Result<String, ApiError> {
+    tokio::task::spawn_blocking(move || Passwords::default().hash(&password))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Password task failed: {}", e)))?
+        .map_err(|_| ApiError::Internal("Failed to hash password".to_string()))
+}
+
+//CHECK A PASSWORD AGAINST ITS STORED HASH, ON THE BLOCKING POOL
+pub async fn verify_password(password: String, stored: String) -> Result<Verification, ApiError> {
+    tokio::task::spawn_blocking(move || Passwords::default().verify(&password, &stored))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Password task failed: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Far below the defaults, so the tests stay quick
+    fn cheap_scheme(m_cost: u32) -> Argon2idScheme {
+        Argon2idScheme { params: Params::new(m_cost, 1, 1, None).unwrap() }
+    }
+
+    fn cheap_passwords() -> Passwords {
+        Passwords::new(Box::new(cheap_scheme(8))).with_legacy(Box::new(LegacySha256Scheme))
+    }
+
+    #[test]
+    fn argon2id_round_trip() {
+        let passwords = Passwords::default();
+        let stored = passwords.hash("correct horse").unwrap();
+
+        assert!(stored.starts_with("$argon2id$v=19$"));
+        assert_eq!(passwords.verify("correct horse", &stored), Verification::Valid { needs_rehash: false });
+        // Salted, the same password never gives the same hash twice
+        assert_ne!(passwords.hash("correct horse").unwrap(), stored);
+    }
+
+    #[test]
+    fn wrong_password_is_invalid() {
+        let passwords = cheap_passwords();
+        let stored = passwords.hash("correct horse").unwrap();
+
+        assert_eq!(passwords.verify("battery staple", &stored), Verification::Invalid);
+        assert_eq!(passwords.verify("", &stored), Verification::Invalid);
+    }
+
+    #[test]
+    fn legacy_sha256_verifies_and_needs_rehash() {
+        let passwords = cheap_passwords();
+        let stored = hex::encode(Sha256::digest(b"correct horse"));
+
+        assert_eq!(passwords.verify("correct horse", &stored), Verification::Valid { needs_rehash: true });
+        assert_eq!(passwords.verify("correct horse", &stored.to_uppercase()), Verification::Valid { needs_rehash: true });
+        assert_eq!(passwords.verify("battery staple", &stored), Verification::Invalid);
+        assert!(LegacySha256Scheme.hash("correct horse").is_err());
+    }
+
+    #[test]
+    fn other_argon2_parameters_are_outdated() {
+        let current = cheap_scheme(8);
+        let stored = cheap_scheme(16).hash("correct horse").unwrap();
+
+        assert!(current.is_outdated(&stored));
+        assert!(!current.is_outdated(&current.hash("correct horse").unwrap()));
+
+        let passwords = Passwords::new(Box::new(current));
+        assert_eq!(passwords.verify("correct horse", &stored), Verification::Valid { needs_rehash: true });
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        let passwords = cheap_passwords();
+        let bcrypt = "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW";
+
+        for stored in ["correct horse", "", bcrypt, "$argon2id$not-a-hash", &"a".repeat(63), &"g".repeat(64)] {
+            assert_eq!(passwords.verify("correct horse", stored), Verification::Invalid, "{:?}", stored);
+        }
+    }
+}
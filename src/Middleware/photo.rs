@@ -0,0 +1,198 @@
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageError, ImageFormat, ImageOutputFormat};
+
+use crate::config::PhotoConfig;
+use crate::errors::ApiError;
+
+const JPEG_QUALITY: u8 = 90;
+// Upper bound on what the decoder may allocate for a single photo
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+//DECODE AN UPLOADED PHOTO AND ENCODE IT AGAIN
+//
+// Only the pixels survive: EXIF (GPS position, camera serial), text chunks and
+// anything appended to the file are dropped. The EXIF orientation is applied
+// to the pixels first. JPEG stays JPEG, every other format becomes PNG
+pub async fn normalize_photo(data: Vec<u8>, config: &PhotoConfig) -> Result<Vec<u8>, ApiError> {
+    let config = config.clone();
+
+    // Decoding is CPU bound, keep it off the async workers
+    tokio::task::spawn_blocking(move || reencode(&data, &config))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Photo task failed: {}", e)))?
+}
+
+fn reencode(data: &[u8], config: &PhotoConfig) -> Result<Vec<u8>, ApiError> {
+    let format = accepted_format(data, config)?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_dimension);
+    limits.max_image_height = Some(config.max_dimension);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = Reader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+
+    // Dimensions are checked against the limits before any pixels are allocated
+    let image = reader.decode().map_err(|e| match e {
+        ImageError::Limits(_) => {
+            ApiError::Validation(format!("Photo must be at most {0}x{0} pixels", config.max_dimension))
+        }
+        e => ApiError::Validation(format!("Photo could not be decoded: {}", e)),
+    })?;
+    let image = apply_orientation(image, exif_orientation(data));
+
+    let mut photo = Vec::new();
+    let encoded = match format {
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut photo, JPEG_QUALITY).encode_image(&image.to_rgb8()),
+        _ => image.write_to(&mut Cursor::new(&mut photo), ImageOutputFormat::Png),
+    };
+    encoded.map_err(|e| ApiError::Internal(format!("Photo could not be encoded: {}", e)))?;
+
+    Ok(photo)
+}
+
+fn accepted_format(data: &[u8], config: &PhotoConfig) -> Result<ImageFormat, ApiError> {
+    match image::guess_format(data) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => Ok(format),
+        Ok(ImageFormat::WebP) if config.accept_webp => Ok(ImageFormat::WebP),
+        Ok(ImageFormat::Gif) if config.accept_gif => Ok(ImageFormat::Gif),
+        _ => {
+            let mut formats = vec!["PNG", "JPEG"];
+            if config.accept_webp {
+                formats.push("WebP");
+            }
+            if config.accept_gif {
+                formats.push("GIF");
+            }
+            let last = formats.pop().unwrap_or_default();
+            Err(ApiError::UnsupportedMediaType(format!("Photo must be a {} or {} image", formats.join(", "), last)))
+        }
+    }
+}
+
+// 1 (upright) when the photo has no EXIF data or no orientation in it
+fn exif_orientation(data: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()
+        .and_then(|exif| exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY).and_then(|field| field.value.get_uint(0)))
+        .unwrap_or(1)
+}
+
+// The EXIF orientation values, as the transform that makes the photo upright
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    // 32x16 so a quarter turn shows up in the dimensions
+    fn encoded(format: ImageOutputFormat) -> Vec<u8> {
+        let image = RgbImage::from_fn(32, 16, |x, y| Rgb([(x * 8) as u8, (y * 16) as u8, 128]));
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(image).write_to(&mut Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    // Inserts a segment right after the JPEG start of image marker
+    fn with_segment(jpeg: Vec<u8>, marker: u8, payload: &[u8]) -> Vec<u8> {
+        let length = (payload.len() + 2) as u16;
+        let mut data = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xFF, marker]);
+        data.extend_from_slice(&length.to_be_bytes());
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    // An APP1 segment holding a big endian TIFF header and one IFD with only the orientation
+    fn with_orientation(jpeg: Vec<u8>, orientation: u16) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend_from_slice(&orientation.to_be_bytes());
+        exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        with_segment(jpeg, 0xE1, &exif)
+    }
+
+    fn dimensions(photo: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory(photo).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[tokio::test]
+    async fn exif_orientation_is_applied_and_dropped() {
+        let data = with_orientation(encoded(ImageOutputFormat::Jpeg(90)), 6);
+        assert_eq!(exif_orientation(&data), 6);
+
+        let photo = normalize_photo(data, &PhotoConfig::default()).await.unwrap();
+
+        assert_eq!(image::guess_format(&photo).unwrap(), ImageFormat::Jpeg);
+        assert_eq!(dimensions(&photo), (16, 32));
+        assert!(exif::Reader::new().read_from_container(&mut Cursor::new(&photo)).is_err());
+    }
+
+    #[tokio::test]
+    async fn metadata_is_stripped() {
+        let mut data = with_segment(encoded(ImageOutputFormat::Jpeg(90)), 0xFE, b"taken at 51.5N 0.1W");
+        data.extend_from_slice(b"appended secret");
+
+        let photo = normalize_photo(data, &PhotoConfig::default()).await.unwrap();
+
+        assert_eq!(dimensions(&photo), (32, 16));
+        for secret in [&b"51.5N"[..], b"appended secret"] {
+            assert!(!photo.windows(secret.len()).any(|window| window == secret));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_photos_are_rejected() {
+        let config = PhotoConfig {
+            max_dimension: 16,
+            ..PhotoConfig::default()
+        };
+
+        let result = normalize_photo(encoded(ImageOutputFormat::Png), &config).await;
+
+        assert!(matches!(result, Err(ApiError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn jpeg_stays_jpeg_and_other_formats_become_png() {
+        let config = PhotoConfig {
+            accept_gif: true,
+            ..PhotoConfig::default()
+        };
+
+        for (format, expected) in [
+            (ImageOutputFormat::Jpeg(90), ImageFormat::Jpeg),
+            (ImageOutputFormat::Png, ImageFormat::Png),
+            (ImageOutputFormat::Gif, ImageFormat::Png),
+        ] {
+            let photo = normalize_photo(encoded(format), &config).await.unwrap();
+            assert_eq!(image::guess_format(&photo).unwrap(), expected);
+            assert_eq!(dimensions(&photo), (32, 16));
+        }
+    }
+
+    #[tokio::test]
+    async fn formats_that_are_not_enabled_are_rejected() {
+        let result = normalize_photo(encoded(ImageOutputFormat::Gif), &PhotoConfig::default()).await;
+
+        assert!(matches!(result, Err(ApiError::UnsupportedMediaType(_))));
+    }
+}
@@ -0,0 +1,380 @@
+use std::io::{Cursor, Read};
+
+use zip::ZipArchive;
+
+//CONTENT SNIFFING FOR UPLOADED FILES
+//
+// The type comes from the bytes alone, never from the filename or a
+// Content-Type sent by the client. Anything unrecognised is
+// application/octet-stream
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+// How much of a file is looked at to tell text from binary
+const TEXT_SNIFF_LEN: usize = 8 * 1024;
+// Archives with more entries are reported as plain ZIP without a closer look
+const MAX_ZIP_ENTRIES: usize = 10_000;
+// Largest [Content_Types].xml or mimetype entry that is inflated
+const MAX_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+struct Signature {
+    offset: usize,
+    magic: &'static [u8],
+    mime: &'static str,
+}
+
+const fn sig(offset: usize, magic: &'static [u8], mime: &'static str) -> Signature {
+    Signature { offset, magic, mime }
+}
+
+// Checked in order, so longer magic numbers come before their prefixes
+const SIGNATURES: &[Signature] = &[
+    // Documents
+    sig(0, b"%PDF-", "application/pdf"),
+    sig(0, b"{\\rtf", "application/rtf"),
+    sig(0, b"%!PS", "application/postscript"),
+    // Images
+    sig(0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    sig(0, b"\xff\xd8\xff", "image/jpeg"),
+    sig(0, b"GIF87a", "image/gif"),
+    sig(0, b"GIF89a", "image/gif"),
+    sig(0, b"II*\x00", "image/tiff"),
+    sig(0, b"MM\x00*", "image/tiff"),
+    sig(4, b"ftypheic", "image/heic"),
+    sig(4, b"ftypavif", "image/avif"),
+    // Audio and video
+    sig(0, b"ID3", "audio/mpeg"),
+    sig(0, b"OggS", "audio/ogg"),
+    sig(0, b"fLaC", "audio/flac"),
+    sig(4, b"ftypM4A ", "audio/mp4"),
+    sig(4, b"ftypqt  ", "video/quicktime"),
+    sig(4, b"ftyp", "video/mp4"),
+    sig(0, b"\x1a\x45\xdf\xa3", "video/webm"),
+    // Archives
+    sig(0, b"\x1f\x8b", "application/gzip"),
+    sig(0, b"BZh", "application/x-bzip2"),
+    sig(0, b"\xfd7zXZ\x00", "application/x-xz"),
+    sig(0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    sig(0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    sig(257, b"ustar", "application/x-tar"),
+    // Executables are named so that rejections say what was sent
+    sig(0, b"MZ", "application/x-msdownload"),
+    sig(0, b"\x7fELF", "application/x-executable"),
+    sig(0, b"\xca\xfe\xba\xbe", "application/java-vm"),
+];
+
+//THE MIME TYPE OF A FILE, FROM ITS CONTENTS
+pub fn detect(data: &[u8]) -> &'static str {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        return detect_zip(data);
+    }
+    if data.starts_with(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+        return detect_ole(data);
+    }
+    if let Some(mime) = detect_riff(data) {
+        return mime;
+    }
+    if is_mpeg_audio_frame(data) {
+        return "audio/mpeg";
+    }
+
+    let matched = SIGNATURES
+        .iter()
+        .find(|s| data.len() >= s.offset + s.magic.len() && &data[s.offset..s.offset + s.magic.len()] == s.magic);
+    if let Some(signature) = matched {
+        return signature.mime;
+    }
+
+    detect_text(data).unwrap_or(OCTET_STREAM)
+}
+
+//CHECK A DETECTED TYPE AGAINST AN ALLOW-LIST, "image/*" MATCHES EVERY IMAGE
+pub fn is_allowed(mime: &str, allowed: &[String]) -> bool {
+    allowed.iter().any(|pattern| match pattern.strip_suffix("/*") {
+        Some(kind) => mime.split('/').next() == Some(kind),
+        None => pattern == mime,
+    })
+}
+
+// RIFF containers carry their real type at offset 8
+fn detect_riff(data: &[u8]) -> Option<&'static str> {
+    if data.len() < 12 || !data.starts_with(b"RIFF") {
+        return None;
+    }
+    match &data[8..12] {
+        b"WEBP" => Some("image/webp"),
+        b"WAVE" => Some("audio/wav"),
+        b"AVI " => Some("video/x-msvideo"),
+        _ => Some(OCTET_STREAM),
+    }
+}
+
+// MP3 files without an ID3 tag start straight with a frame header
+fn is_mpeg_audio_frame(data: &[u8]) -> bool {
+    data.len() >= 3 && data[0] == 0xff && matches!(data[1], 0xfb | 0xf3 | 0xf2) && data[2] & 0xf0 != 0xf0
+}
+
+// Legacy Office files are OLE compound documents. The application is told
+// apart by the stream names in the directory, which are UTF-16
+fn detect_ole(data: &[u8]) -> &'static str {
+    let contains = |name: &str| {
+        let utf16: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        data.windows(utf16.len()).any(|window| window == utf16.as_slice())
+    };
+
+    if contains("WordDocument") {
+        "application/msword"
+    } else if contains("Workbook") || contains("Book") {
+        "application/vnd.ms-excel"
+    } else if contains("PowerPoint Document") {
+        "application/vnd.ms-powerpoint"
+    } else {
+        "application/x-ole-storage"
+    }
+}
+
+// An OOXML package: the main part that must exist and the content type
+// [Content_Types].xml must declare for it
+struct OfficeFormat {
+    main_part: &'static str,
+    content_type: &'static str,
+    mime: &'static str,
+}
+
+const OFFICE_FORMATS: &[OfficeFormat] = &[
+    OfficeFormat {
+        main_part: "word/document.xml",
+        content_type: "application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml",
+        mime: "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    },
+    OfficeFormat {
+        main_part: "word/document.xml",
+        content_type: "application/vnd.ms-word.document.macroEnabled.main+xml",
+        mime: "application/vnd.ms-word.document.macroEnabled.12",
+    },
+    OfficeFormat {
+        main_part: "xl/workbook.xml",
+        content_type: "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml",
+        mime: "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    },
+    OfficeFormat {
+        main_part: "xl/workbook.xml",
+        content_type: "application/vnd.ms-excel.sheet.macroEnabled.main+xml",
+        mime: "application/vnd.ms-excel.sheet.macroEnabled.12",
+    },
+    OfficeFormat {
+        main_part: "ppt/presentation.xml",
+        content_type: "application/vnd.openxmlformats-officedocument.presentationml.presentation.main+xml",
+        mime: "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    },
+    OfficeFormat {
+        main_part: "ppt/presentation.xml",
+        content_type: "application/vnd.ms-powerpoint.presentation.macroEnabled.main+xml",
+        mime: "application/vnd.ms-powerpoint.presentation.macroEnabled.12",
+    },
+];
+
+// Types a "mimetype" entry may name in OpenDocument and EPUB files
+const ZIP_MIMETYPES: &[&str] = &[
+    "application/vnd.oasis.opendocument.text",
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.presentation",
+    "application/epub+zip",
+];
+
+// A ZIP only counts as an Office document when its central directory can be
+// read and the package has the parts that format requires. Anything less,
+// such as a ZIP holding only a stray [Content_Types].xml, is application/zip
+fn detect_zip(data: &[u8]) -> &'static str {
+    let mut archive = match ZipArchive::new(Cursor::new(data)) {
+        Ok(archive) => archive,
+        Err(_) => return OCTET_STREAM,
+    };
+    if archive.len() > MAX_ZIP_ENTRIES {
+        return "application/zip";
+    }
+
+    if let Some(content_types) = read_entry(&mut archive, "[Content_Types].xml") {
+        if archive.by_name("_rels/.rels").is_ok() {
+            let declared = |content_type: &str| content_types.contains(&format!("ContentType=\"{}\"", content_type));
+            let format = OFFICE_FORMATS
+                .iter()
+                .find(|format| declared(format.content_type) && archive.file_names().any(|name| name == format.main_part));
+            if let Some(format) = format {
+                return format.mime;
+            }
+        }
+        return "application/zip";
+    }
+
+    // OpenDocument and EPUB name their type in a stored first entry
+    let first_is_mimetype = archive.by_index_raw(0).map(|entry| entry.name() == "mimetype").unwrap_or(false);
+    if first_is_mimetype {
+        if let Some(mimetype) = read_entry(&mut archive, "mimetype") {
+            if let Some(mime) = ZIP_MIMETYPES.iter().find(|mime| **mime == mimetype.trim()) {
+                return mime;
+            }
+        }
+    }
+
+    if archive.by_name("META-INF/MANIFEST.MF").is_ok() {
+        return "application/java-archive";
+    }
+
+    "application/zip"
+}
+
+// A small text entry, or None when it is missing, too large or not UTF-8
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Option<String> {
+    let entry = archive.by_name(name).ok()?;
+    if entry.size() > MAX_MANIFEST_SIZE {
+        return None;
+    }
+
+    // The declared size can lie, so the read is capped as well
+    let mut text = String::new();
+    entry.take(MAX_MANIFEST_SIZE).read_to_string(&mut text).ok()?;
+    Some(text)
+}
+
+// Text is valid UTF-8 (or has a UTF-16 BOM) without control characters other
+// than whitespace. Markup is told apart, since HTML and SVG must never be
+// mistaken for harmless text
+fn detect_text(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\xff\xfe") || data.starts_with(b"\xfe\xff") {
+        return Some("text/plain");
+    }
+
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let head = &data[..data.len().min(TEXT_SNIFF_LEN)];
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // Cut off in the middle of a character at the end of the sample
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c')) {
+        return None;
+    }
+
+    let start = text.trim_start().to_ascii_lowercase();
+    let mime = if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html"
+    } else if start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg")) {
+        "image/svg+xml"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
+    };
+    Some(mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    use super::*;
+
+    fn zip_of(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, contents) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn with_tail(magic: &[u8]) -> Vec<u8> {
+        let mut data = magic.to_vec();
+        data.extend_from_slice(&[0u8; 64]);
+        data
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(detect(b"%PDF-1.7\n..."), "application/pdf");
+        assert_eq!(detect(&with_tail(b"\x89PNG\r\n\x1a\n")), "image/png");
+        assert_eq!(detect(&with_tail(b"\xff\xd8\xff\xe0")), "image/jpeg");
+        assert_eq!(detect(&with_tail(b"GIF89a")), "image/gif");
+        assert_eq!(detect(&with_tail(b"RIFF\0\0\0\0WEBPVP8 ")), "image/webp");
+        assert_eq!(detect(&with_tail(b"RIFF\0\0\0\0WAVEfmt ")), "audio/wav");
+        assert_eq!(detect(&with_tail(b"\xff\xfb\x90")), "audio/mpeg");
+        assert_eq!(detect(&with_tail(b"\0\0\0\x20ftypM4A ")), "audio/mp4");
+        assert_eq!(detect(&with_tail(b"\0\0\0\x20ftypisom")), "video/mp4");
+        assert_eq!(detect(&with_tail(b"MZ\x90\0")), "application/x-msdownload");
+
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(detect(&tar), "application/x-tar");
+    }
+
+    #[test]
+    fn legacy_office_by_stream_name() {
+        let mut doc = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1".to_vec();
+        doc.extend("WordDocument".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(detect(&doc), "application/msword");
+        assert_eq!(detect(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1\0\0"), "application/x-ole-storage");
+    }
+
+    #[test]
+    fn ooxml_needs_its_main_part() {
+        let content_types = r#"<Types><Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/></Types>"#;
+        let docx = zip_of(&[("[Content_Types].xml", content_types), ("_rels/.rels", "<Relationships/>"), ("word/document.xml", "<w:document/>")]);
+        assert_eq!(detect(&docx), "application/vnd.openxmlformats-officedocument.wordprocessingml.document");
+
+        // Declared but missing, or only a stray [Content_Types].xml
+        let no_main_part = zip_of(&[("[Content_Types].xml", content_types), ("_rels/.rels", "<Relationships/>")]);
+        assert_eq!(detect(&no_main_part), "application/zip");
+        let no_rels = zip_of(&[("[Content_Types].xml", content_types), ("word/document.xml", "<w:document/>")]);
+        assert_eq!(detect(&no_rels), "application/zip");
+    }
+
+    #[test]
+    fn other_zips() {
+        assert_eq!(detect(&zip_of(&[("notes.txt", "hello")])), "application/zip");
+        assert_eq!(detect(&zip_of(&[("mimetype", "application/vnd.oasis.opendocument.text"), ("content.xml", "<x/>")])), "application/vnd.oasis.opendocument.text");
+        // The mimetype entry only counts when it comes first
+        assert_eq!(detect(&zip_of(&[("content.xml", "<x/>"), ("mimetype", "application/vnd.oasis.opendocument.text")])), "application/zip");
+        assert_eq!(detect(&zip_of(&[("META-INF/MANIFEST.MF", "Manifest-Version: 1.0")])), "application/java-archive");
+        // A local header with no readable central directory
+        assert_eq!(detect(b"PK\x03\x04garbage"), OCTET_STREAM);
+    }
+
+    #[test]
+    fn text_or_binary() {
+        assert_eq!(detect(b"plain words\r\n\tand more"), "text/plain");
+        assert_eq!(detect("h\u{e9}llo w\u{f6}rld".as_bytes()), "text/plain");
+        assert_eq!(detect(b"\xef\xbb\xbfwith a BOM"), "text/plain");
+        assert_eq!(detect(b"\xff\xfeh\0i\0"), "text/plain");
+        assert_eq!(detect(b"text with a \0 NUL"), OCTET_STREAM);
+        assert_eq!(detect(b"not \xc3\x28 UTF-8"), OCTET_STREAM);
+
+        // A character cut off by the end of the sample is still text
+        let mut long = "a".repeat(TEXT_SNIFF_LEN - 1).into_bytes();
+        long.extend_from_slice("\u{e9}".as_bytes());
+        assert_eq!(detect(&long), "text/plain");
+    }
+
+    #[test]
+    fn markup_is_not_plain_text() {
+        assert_eq!(detect(b"  <!DOCTYPE html><html></html>"), "text/html");
+        assert_eq!(detect(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), "image/svg+xml");
+        assert_eq!(detect(b"<?xml version=\"1.0\"?>\n<svg/>"), "image/svg+xml");
+        assert_eq!(detect(b"<?xml version=\"1.0\"?>\n<note/>"), "application/xml");
+    }
+
+    #[test]
+    fn allow_list_wildcards() {
+        let allowed = vec!["audio/*".to_string(), "application/pdf".to_string()];
+        assert!(is_allowed("audio/ogg", &allowed));
+        assert!(is_allowed("application/pdf", &allowed));
+        assert!(!is_allowed("application/zip", &allowed));
+        assert!(!is_allowed("audiobook/x", &allowed));
+    }
+}
@@ -0,0 +1,541 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::models::user::Role;
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const MIN_JWT_SECRET_LEN: usize = 32;
+// Keeps a single decoded photo within a few hundred MB of memory
+const MAX_PHOTO_DIMENSION: u32 = 8192;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(String, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Env(name, e) => write!(f, "invalid value in {}: {}", name, e),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "127.0.0.1:8000".to_string(),
+            cors_origins: vec!["http://localhost:3000".to_string(), "http://localhost:8000".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { url: "sqlite://sqlite.db".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub jwt_secret: String,
+    pub access_token_minutes: i64,
+    pub refresh_token_days: i64,
+    // Made an admin at startup while there is none, so someone can hand out roles
+    pub initial_admin: Option<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            jwt_secret: String::new(),
+            access_token_minutes: 20,
+            refresh_token_days: 14,
+            initial_admin: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    // Blobs stay in their sqlite rows
+    #[default]
+    Sqlite,
+    // Blobs are written under storage.path, named by their SHA-256
+    Filesystem,
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            "filesystem" => Ok(StorageBackend::Filesystem),
+            other => Err(format!("unknown storage backend {:?}, expected sqlite or filesystem", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    // Root directory of the filesystem backend. Also read from when the
+    // backend is sqlite, for blobs that were stored on disk before
+    pub path: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Sqlite,
+            path: PathBuf::from("uploads"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhotoConfig {
+    // Photos wider or taller than this are rejected before they are decoded
+    pub max_dimension: u32,
+    // Also take WebP and GIF uploads. They are stored as PNG like any other photo
+    pub accept_webp: bool,
+    pub accept_gif: bool,
+}
+
+impl Default for PhotoConfig {
+    fn default() -> Self {
+        PhotoConfig {
+            max_dimension: 2048,
+            accept_webp: false,
+            accept_gif: false,
+        }
+    }
+}
+
+// Types post attachments may have unless configured otherwise
+const DEFAULT_ALLOWED_UPLOADS: &[&str] = &[
+    "application/pdf",
+    "application/rtf",
+    "text/plain",
+    "application/msword",
+    "application/vnd.ms-excel",
+    "application/vnd.ms-powerpoint",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "application/vnd.oasis.opendocument.text",
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.presentation",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "audio/*",
+    "application/zip",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadsConfig {
+    // Sniffed MIME types accepted for post attachments. "audio/*" matches
+    // every audio type
+    pub allowed: Vec<String>,
+    // Per endpoint replacements for allowed
+    pub create_post: Option<Vec<String>>,
+    pub update_post: Option<Vec<String>>,
+    pub post_files: Option<Vec<String>>,
+    // Resumable uploads nobody has added to for this long are deleted
+    pub resumable_expiry_hours: i64,
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        UploadsConfig {
+            allowed: DEFAULT_ALLOWED_UPLOADS.iter().map(|mime| mime.to_string()).collect(),
+            create_post: None,
+            update_post: None,
+            post_files: None,
+            resumable_expiry_hours: 24,
+        }
+    }
+}
+
+impl UploadsConfig {
+    // POST /api/posts
+    pub fn create_post(&self) -> &[String] {
+        self.create_post.as_deref().unwrap_or(&self.allowed)
+    }
+
+    // PATCH /api/posts/{id}
+    pub fn update_post(&self) -> &[String] {
+        self.update_post.as_deref().unwrap_or(&self.allowed)
+    }
+
+    // POST /api/posts/{id}/files
+    pub fn post_files(&self) -> &[String] {
+        self.post_files.as_deref().unwrap_or(&self.allowed)
+    }
+}
+
+// A storage limit in MB, or "unlimited"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Quota {
+    Megabytes(u64),
+    Unlimited(UnlimitedQuota),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnlimitedQuota {
+    Unlimited,
+}
+
+impl Quota {
+    pub fn bytes(&self) -> Option<i64> {
+        match self {
+            Quota::Megabytes(megabytes) => Some(megabytes.saturating_mul(1024 * 1024).min(i64::MAX as u64) as i64),
+            Quota::Unlimited(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Quota {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "unlimited" => Ok(Quota::Unlimited(UnlimitedQuota::Unlimited)),
+            other => other
+                .parse()
+                .map(Quota::Megabytes)
+                .map_err(|_| format!("expected a number of MB or unlimited, got {:?}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    // Space each user's attachments may take, by role. A user's own
+    // storage_quota, set by an admin, takes precedence
+    pub reader_mb: Quota,
+    pub editor_mb: Quota,
+    pub admin_mb: Quota,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            reader_mb: Quota::Megabytes(0),
+            editor_mb: Quota::Megabytes(500),
+            admin_mb: Quota::Unlimited(UnlimitedQuota::Unlimited),
+        }
+    }
+}
+
+impl QuotaConfig {
+    // In bytes, None when the role has no limit
+    pub fn for_role(&self, role: Role) -> Option<i64> {
+        match role {
+            Role::Reader => self.reader_mb.bytes(),
+            Role::Editor => self.editor_mb.bytes(),
+            Role::Admin => self.admin_mb.bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanBackend {
+    // Uploads are stored without being scanned
+    #[default]
+    None,
+    // Only flags the EICAR test file. For trying out quarantine, not protection
+    Eicar,
+    // A ClamAV daemon at scanning.clamd_address
+    Clamd,
+}
+
+impl std::str::FromStr for ScanBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(ScanBackend::None),
+            "eicar" => Ok(ScanBackend::Eicar),
+            "clamd" => Ok(ScanBackend::Clamd),
+            other => Err(format!("unknown scan backend {:?}, expected none, eicar or clamd", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    pub backend: ScanBackend,
+    // host:port of clamd's TCP socket, or unix:/path/to/clamd.ctl
+    pub clamd_address: String,
+    // A scan taking longer fails, and so does the upload
+    pub timeout_seconds: u64,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            backend: ScanBackend::None,
+            clamd_address: "127.0.0.1:3310".to_string(),
+            timeout_seconds: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    // Events older than this are pruned, clients can't catch up on them
+    pub retention_hours: i64,
+    // Only the newest this many events are kept, whatever their age
+    pub max_rows: i64,
+    // A client that missed more is told to reload instead
+    pub max_replay: i64,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            retention_hours: 7 * 24,
+            max_rows: 100_000,
+            max_replay: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub storage: StorageConfig,
+    pub photos: PhotoConfig,
+    pub uploads: UploadsConfig,
+    pub quotas: QuotaConfig,
+    pub scanning: ScanConfig,
+    pub events: EventsConfig,
+}
+
+impl Config {
+    // Reads the TOML file named by APP_CONFIG (or ./config.toml if present),
+    // then applies APP_* environment overrides and validates the result.
+    pub fn load() -> Result<Config, ConfigError> {
+        let mut config = match std::env::var("APP_CONFIG") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            Err(_) => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(value) = env_var("APP_BIND_ADDRESS") {
+            self.server.bind_address = value;
+        }
+        if let Some(value) = env_var("APP_CORS_ORIGINS") {
+            self.server.cors_origins = value.split(',').map(|origin| origin.trim().to_string()).filter(|origin| !origin.is_empty()).collect();
+        }
+        if let Some(value) = env_var("APP_DATABASE_URL") {
+            self.database.url = value;
+        }
+        if let Some(value) = env_var("APP_JWT_SECRET") {
+            self.auth.jwt_secret = value;
+        }
+        if let Some(value) = env_var("APP_ACCESS_TOKEN_MINUTES") {
+            self.auth.access_token_minutes = parse_env("APP_ACCESS_TOKEN_MINUTES", &value)?;
+        }
+        if let Some(value) = env_var("APP_REFRESH_TOKEN_DAYS") {
+            self.auth.refresh_token_days = parse_env("APP_REFRESH_TOKEN_DAYS", &value)?;
+        }
+        if let Some(value) = env_var("APP_INITIAL_ADMIN") {
+            self.auth.initial_admin = Some(value).filter(|username| !username.trim().is_empty());
+        }
+        if let Some(value) = env_var("APP_STORAGE_BACKEND") {
+            self.storage.backend = parse_env("APP_STORAGE_BACKEND", &value)?;
+        }
+        if let Some(value) = env_var("APP_STORAGE_PATH") {
+            self.storage.path = PathBuf::from(value);
+        }
+        if let Some(value) = env_var("APP_PHOTO_MAX_DIMENSION") {
+            self.photos.max_dimension = parse_env("APP_PHOTO_MAX_DIMENSION", &value)?;
+        }
+        if let Some(value) = env_var("APP_PHOTO_ACCEPT_WEBP") {
+            self.photos.accept_webp = parse_env("APP_PHOTO_ACCEPT_WEBP", &value)?;
+        }
+        if let Some(value) = env_var("APP_PHOTO_ACCEPT_GIF") {
+            self.photos.accept_gif = parse_env("APP_PHOTO_ACCEPT_GIF", &value)?;
+        }
+        if let Some(value) = env_var("APP_UPLOADS_RESUMABLE_EXPIRY_HOURS") {
+            self.uploads.resumable_expiry_hours = parse_env("APP_UPLOADS_RESUMABLE_EXPIRY_HOURS", &value)?;
+        }
+        if let Some(value) = env_var("APP_QUOTA_READER_MB") {
+            self.quotas.reader_mb = parse_env("APP_QUOTA_READER_MB", &value)?;
+        }
+        if let Some(value) = env_var("APP_QUOTA_EDITOR_MB") {
+            self.quotas.editor_mb = parse_env("APP_QUOTA_EDITOR_MB", &value)?;
+        }
+        if let Some(value) = env_var("APP_QUOTA_ADMIN_MB") {
+            self.quotas.admin_mb = parse_env("APP_QUOTA_ADMIN_MB", &value)?;
+        }
+        if let Some(value) = env_var("APP_SCAN_BACKEND") {
+            self.scanning.backend = parse_env("APP_SCAN_BACKEND", &value)?;
+        }
+        if let Some(value) = env_var("APP_SCAN_CLAMD_ADDRESS") {
+            self.scanning.clamd_address = value;
+        }
+        if let Some(value) = env_var("APP_SCAN_TIMEOUT_SECONDS") {
+            self.scanning.timeout_seconds = parse_env("APP_SCAN_TIMEOUT_SECONDS", &value)?;
+        }
+        if let Some(value) = env_var("APP_EVENTS_RETENTION_HOURS") {
+            self.events.retention_hours = parse_env("APP_EVENTS_RETENTION_HOURS", &value)?;
+        }
+        if let Some(value) = env_var("APP_EVENTS_MAX_ROWS") {
+            self.events.max_rows = parse_env("APP_EVENTS_MAX_ROWS", &value)?;
+        }
+        if let Some(value) = env_var("APP_EVENTS_MAX_REPLAY") {
+            self.events.max_replay = parse_env("APP_EVENTS_MAX_REPLAY", &value)?;
+        }
+        if let Some(value) = env_var("APP_UPLOADS_ALLOWED") {
+            self.uploads.allowed = value.split(',').map(|mime| mime.trim().to_string()).filter(|mime| !mime.is_empty()).collect();
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.bind_address()?;
+
+        if !self.database.url.starts_with("sqlite:") {
+            return Err(ConfigError::Invalid(format!(
+                "database.url must be a sqlite: URL, got {:?}",
+                self.database.url
+            )));
+        }
+        if self.auth.jwt_secret.len() < MIN_JWT_SECRET_LEN {
+            return Err(ConfigError::Invalid(format!(
+                "auth.jwt_secret (or APP_JWT_SECRET) must be at least {} characters long",
+                MIN_JWT_SECRET_LEN
+            )));
+        }
+        if self.auth.access_token_minutes <= 0 || self.auth.refresh_token_days <= 0 {
+            return Err(ConfigError::Invalid(
+                "auth.access_token_minutes and auth.refresh_token_days must be positive".to_string(),
+            ));
+        }
+        if self.storage.path.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid("storage.path must not be empty".to_string()));
+        }
+        if self.photos.max_dimension == 0 || self.photos.max_dimension > MAX_PHOTO_DIMENSION {
+            return Err(ConfigError::Invalid(format!(
+                "photos.max_dimension must be between 1 and {}",
+                MAX_PHOTO_DIMENSION
+            )));
+        }
+        if self.uploads.resumable_expiry_hours <= 0 {
+            return Err(ConfigError::Invalid("uploads.resumable_expiry_hours must be positive".to_string()));
+        }
+        let upload_lists = [
+            ("uploads.allowed", Some(&self.uploads.allowed)),
+            ("uploads.create_post", self.uploads.create_post.as_ref()),
+            ("uploads.update_post", self.uploads.update_post.as_ref()),
+            ("uploads.post_files", self.uploads.post_files.as_ref()),
+        ];
+        for (name, list) in upload_lists {
+            for mime in list.into_iter().flatten() {
+                let valid = match mime.split_once('/') {
+                    Some((kind, subtype)) => !kind.is_empty() && !subtype.is_empty() && *mime == mime.to_ascii_lowercase(),
+                    None => false,
+                };
+                if !valid {
+                    return Err(ConfigError::Invalid(format!(
+                        "{} entry {:?} must be a lowercase MIME type like image/png or image/*",
+                        name, mime
+                    )));
+                }
+            }
+        }
+        if self.scanning.timeout_seconds == 0 {
+            return Err(ConfigError::Invalid("scanning.timeout_seconds must be positive".to_string()));
+        }
+        if self.scanning.backend == ScanBackend::Clamd && self.scanning.clamd_address.is_empty() {
+            return Err(ConfigError::Invalid("scanning.clamd_address must be set when the backend is clamd".to_string()));
+        }
+        if self.events.retention_hours <= 0 || self.events.max_rows <= 0 || self.events.max_replay <= 0 {
+            return Err(ConfigError::Invalid(
+                "events.retention_hours, events.max_rows and events.max_replay must be positive".to_string(),
+            ));
+        }
+        for origin in &self.server.cors_origins {
+            if !(origin.starts_with("http://") || origin.starts_with("https://")) {
+                return Err(ConfigError::Invalid(format!(
+                    "server.cors_origins entry {:?} must start with http:// or https://",
+                    origin
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bind_address(&self) -> Result<SocketAddr, ConfigError> {
+        self.server.bind_address.parse().map_err(|_| {
+            ConfigError::Invalid(format!(
+                "server.bind_address must look like 127.0.0.1:8000, got {:?}",
+                self.server.bind_address
+            ))
+        })
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| ConfigError::Env(name.to_string(), e.to_string()))
+}
@@ -1,28 +1,46 @@
+use std::sync::Arc;
 use sqlx::SqlitePool;
 use warp::Filter;
 use crate::routes::author_routes::*;
+use crate::routes::events_routes::*;
+use crate::routes::files_routes::*;
 use crate::routes::posts_routes::*;
+use crate::routes::uploads_routes::*;
 use crate::routes::user_routes::*;
 use crate::ws::clients::Clients;
+use crate::config::Config;
+use crate::storage::Blobs;
+use crate::errors::handle_rejection;
+use crate::handlers::uploads_handler::TUS_VERSION;
+use warp::http::header::HeaderValue;
+use warp::http::StatusCode;
+use warp::path::FullPath;
 
 
 
-pub fn routes(db: &SqlitePool, clients: Clients) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
-    let author_filter =  get_author_route(db.clone());
+pub fn routes(db: &SqlitePool, clients: Clients, config: Arc<Config>, blobs: Blobs) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    let author_filter =  get_author_route(db.clone(), config.clone());
     let authors_filter = get_authors(db.clone());
-    let post_author_filter = post_author_route(db.clone(), clients.clone());
+    let post_author_filter = post_author_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
     let posts_filter = get_posts(db.clone());
-    let post_filter = get_post_route(db.clone());
-    let create_post_filter = create_post_route(db.clone(), clients.clone());
-    let get_posts_by_author = get_posts_by_author(db.clone());
-    let update_post_filter = update_post_route(db.clone(), clients.clone());
-    let update_author_filter = update_author_route(db.clone(), clients.clone());
-    let delete_post_filter = delete_post_route(db.clone(), clients.clone());
-    let delete_author_filter = delete_author_route(db.clone(), clients.clone());
+    let post_filter = get_post_route(db.clone(), config.clone());
+    let create_post_filter = create_post_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
+    let get_posts_by_author = get_posts_by_author(db.clone(), config.clone());
+    let update_post_filter = update_post_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
+    let update_author_filter = update_author_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
+    let delete_post_filter = delete_post_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
+    let delete_author_filter = delete_author_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
     let register_user_filter = register_user_route(db.clone());
-    let login_user_filter = login_user_route(db.clone());
-    let dowload_file_filter = download_file(db.clone());
-    // let upload = upload_route();
+    let login_user_filter = login_user_route(db.clone(), config.clone());
+    let refresh_token_filter = refresh_token_route(db.clone(), config.clone());
+    let logout_filter = logout_route(db.clone(), config.clone());
+    let update_role_filter = update_role_route(db.clone(), config.clone());
+    let update_quota_filter = update_quota_route(db.clone(), config.clone());
+    let usage_filter = usage_route(db.clone(), config.clone());
+    let upload_post_files_filter = upload_post_files_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
+    let get_post_files_filter = get_post_files_route(db.clone(), config.clone());
+    let upload_author_photo_filter = upload_author_photo_route(db.clone(), config.clone(), clients.clone(), blobs.clone());
+    let dowload_file_filter = download_file(db.clone(), config, blobs);
    
    
    
@@ -39,6 +57,59 @@ pub fn routes(db: &SqlitePool, clients: Clients) -> impl Filter<Extract = impl w
     .or(delete_post_filter)
     .or(register_user_filter)
     .or(login_user_filter)
+    .or(refresh_token_filter)
+    .or(logout_filter)
+    .or(update_role_filter)
+    .or(update_quota_filter)
+    .or(usage_filter)
+    .or(upload_post_files_filter)
+    .or(get_post_files_filter)
+    .or(upload_author_photo_filter)
     .or(dowload_file_filter)
 
 }
+
+
+// Served without the JSON content type check, browsers open these directly
+pub fn file_routes(db: &SqlitePool, config: Arc<Config>, blobs: Blobs) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    get_file_route(db.clone(), config.clone(), blobs.clone())
+    .or(get_post_files_zip_route(db.clone(), config, blobs.clone()))
+    .or(get_author_photo_route(db.clone(), blobs))
+}
+
+
+// EventSource asks for text/event-stream, so this skips the JSON content type check too
+pub fn event_routes(db: &SqlitePool, clients: Clients, config: Arc<Config>) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    get_events_route(db.clone(), config, clients)
+}
+
+
+// tus clients read Tus-Resumable from every response, errors included, so these
+// routes turn their own rejections into responses. Only requests under
+// /api/uploads get that far, everything else falls through untouched
+pub fn upload_routes(db: &SqlitePool, config: Arc<Config>) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    let tus = tus_options_route()
+        .or(create_upload_route(db.clone(), config.clone()))
+        .or(head_upload_route(db.clone(), config.clone()))
+        .or(patch_upload_route(db.clone(), config.clone()))
+        .or(delete_upload_route(db.clone(), config));
+
+    warp::path::full()
+        .and_then(|path: FullPath| async move {
+            if path.as_str() == "/api/uploads" || path.as_str().starts_with("/api/uploads/") {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(tus.recover(handle_rejection))
+        .map(|reply| {
+            let mut response = warp::reply::Reply::into_response(reply);
+            response.headers_mut().insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+            if response.status() == StatusCode::PRECONDITION_FAILED {
+                response.headers_mut().insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+            }
+            response
+        })
+}
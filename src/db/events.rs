@@ -0,0 +1,70 @@
+use chrono::{Duration, Utc};
+use sqlx::{Row, SqlitePool};
+
+use crate::config::EventsConfig;
+use crate::errors::ApiError;
+use crate::ws::events::Event;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// What a client reconnecting after some seq is sent
+pub enum Replay {
+    // Everything after it, oldest first
+    Events(Vec<Event>),
+    // Too much, or events that were already pruned. Holds the newest seq,
+    // which the client can continue from once it has reloaded
+    Reset(i64),
+}
+
+//STORE AN EVENT, RETURNS ITS SEQ
+pub async fn insert_event(db: &SqlitePool, event: &Event) -> Result<i64, ApiError> {
+    let payload = serde_json::to_string(event).map_err(|e| ApiError::Internal(e.to_string()))?;
+    let result = sqlx::query("INSERT INTO events (payload) VALUES (?)").bind(payload).execute(db).await?;
+    Ok(result.last_insert_rowid())
+}
+
+//THE EVENTS AFTER `since`, IF THEY ARE ALL STILL KEPT AND THERE AREN'T TOO MANY
+pub async fn replay_events(db: &SqlitePool, since: i64, max_replay: i64) -> Result<Replay, ApiError> {
+    // sqlite_sequence remembers the newest seq even when every row was pruned
+    let last: i64 = sqlx::query_scalar("SELECT seq FROM sqlite_sequence WHERE name = 'events'")
+        .fetch_optional(db)
+        .await?
+        .unwrap_or(0);
+    let oldest: Option<i64> = sqlx::query_scalar("SELECT MIN(seq) FROM events").fetch_one(db).await?;
+    let oldest = oldest.unwrap_or(last + 1);
+    if since > last || since + 1 < oldest {
+        return Ok(Replay::Reset(last));
+    }
+
+    let missed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM events WHERE seq > ?").bind(since).fetch_one(db).await?;
+    if missed > max_replay {
+        return Ok(Replay::Reset(last));
+    }
+
+    let rows = sqlx::query("SELECT seq, payload FROM events WHERE seq > ? ORDER BY seq").bind(since).fetch_all(db).await?;
+    let mut events = Vec::with_capacity(rows.len());
+    for row in rows {
+        let seq: i64 = row.get("seq");
+        let payload: String = row.get("payload");
+        match serde_json::from_str::<Event>(&payload) {
+            Ok(event) => events.push(Event { seq, ..event }),
+            Err(e) => eprintln!("Skipping stored event {} that can't be read: {}", seq, e),
+        }
+    }
+    Ok(Replay::Events(events))
+}
+
+//DELETE EVENTS PAST THE RETENTION PERIOD OR BEYOND THE NEWEST max_rows, RETURNS HOW MANY
+pub async fn prune_events(db: &SqlitePool, config: &EventsConfig) -> Result<u64, ApiError> {
+    let cutoff = (Utc::now() - Duration::hours(config.retention_hours)).format(TIMESTAMP_FORMAT).to_string();
+
+    let mut tx = db.begin().await?;
+    let expired = sqlx::query("DELETE FROM events WHERE created_at <= ?").bind(&cutoff).execute(&mut *tx).await?;
+    let excess = sqlx::query("DELETE FROM events WHERE seq <= (SELECT seq FROM events ORDER BY seq DESC LIMIT 1 OFFSET ?)")
+        .bind(config.max_rows)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(expired.rows_affected() + excess.rows_affected())
+}
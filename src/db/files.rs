@@ -0,0 +1,422 @@
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::errors::ApiError;
+use crate::models::files::FileMetadata;
+use crate::Middleware::sniff;
+use crate::scanner::{ScanReport, ScanStatus, UploadScanner};
+use crate::storage::{BlobOwner, BlobStore, Blobs};
+
+const MAX_FILENAME_LEN: usize = 255;
+// Rows hashed per round trip when backfilling checksums
+const BACKFILL_BATCH: i64 = 50;
+
+// A file received from a client, before it is stored
+pub struct NewFile {
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+    // Sniffed from data, the client's claims about the type are ignored
+    pub mime_type: &'static str,
+    // Unscanned until `scan` is called
+    pub scan: ScanReport,
+}
+
+impl NewFile {
+    pub fn new(filename: Option<&str>, data: Vec<u8>) -> NewFile {
+        let mime_type = sniff::detect(&data);
+        NewFile { filename: filename.and_then(sanitize_filename), data, mime_type, scan: ScanReport::unscanned() }
+    }
+
+    // Run the configured malware scanner. A flagged file is still stored,
+    // but quarantined
+    pub async fn scan(&mut self, scanner: &UploadScanner) -> Result<(), ApiError> {
+        self.scan = scanner.scan(&self.data).await?;
+        Ok(())
+    }
+
+    // 415 naming the detected type when the endpoint doesn't take it
+    pub fn check_allowed(&self, allowed: &[String]) -> Result<(), ApiError> {
+        if sniff::is_allowed(self.mime_type, allowed) {
+            return Ok(());
+        }
+
+        Err(ApiError::UnsupportedFileType {
+            filename: self.filename.clone(),
+            detected: self.mime_type.to_string(),
+            allowed: allowed.to_vec(),
+        })
+    }
+}
+
+// Keep only the last path component and drop control characters, so a
+// filename can be echoed back in headers and listings safely
+fn sanitize_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let name: String = name.chars().filter(|c| !c.is_control()).take(MAX_FILENAME_LEN).collect();
+    let name = name.trim();
+
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+//ADD THE ROWS OF A POST'S NEW FILES, WITHOUT THEIR CONTENT
+//
+// Meant for the ImmediateTx that checked the quota. `store_files` fills in
+// the content once it has committed, the blob stores write through the pool
+pub async fn insert_file_rows(
+    conn: &mut SqliteConnection,
+    blobs: &Blobs,
+    post_id: i64,
+    uploaded_by: i64,
+    files: &[NewFile],
+) -> Result<Vec<FileMetadata>, ApiError> {
+    let uploaded_at = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let storage = blobs.writer().name();
+    let mut stored = Vec::new();
+
+    for file in files {
+        let query = "
+            INSERT INTO files (post_id, filename, mime_type, size, sha256, uploaded_at, storage, uploaded_by, scan_status, scan_result)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ";
+
+        let metadata = FileMetadata {
+            id: 0,
+            post_id,
+            filename: file.filename.clone(),
+            mime_type: file.mime_type.to_string(),
+            size: file.data.len() as i64,
+            sha256: Some(sha256_hex(&file.data)),
+            uploaded_at: Some(uploaded_at.clone()),
+            scan_status: file.scan.status.as_str().to_string(),
+            scan_result: file.scan.result.clone(),
+            storage: storage.to_string(),
+        };
+
+        let result = sqlx::query(query)
+            .bind(post_id)
+            .bind(&metadata.filename)
+            .bind(&metadata.mime_type)
+            .bind(metadata.size)
+            .bind(&metadata.sha256)
+            .bind(&metadata.uploaded_at)
+            .bind(&metadata.storage)
+            .bind(uploaded_by)
+            .bind(&metadata.scan_status)
+            .bind(&metadata.scan_result)
+            .execute(&mut *conn)
+            .await?;
+
+        stored.push(FileMetadata { id: result.last_insert_rowid(), ..metadata });
+    }
+
+    Ok(stored)
+}
+
+//SAVE THE CONTENT OF ROWS ADDED BY `insert_file_rows`, IN THE SAME ORDER AS ITS FILES
+pub async fn store_files(db: &SqlitePool, blobs: &Blobs, stored: &[FileMetadata], files: &[NewFile]) -> Result<(), ApiError> {
+    let store = blobs.writer();
+
+    for (index, (metadata, file)) in stored.iter().zip(files).enumerate() {
+        if let Err(e) = store.put(BlobOwner::File(metadata.id), metadata.sha256.as_deref().unwrap_or_default(), &file.data).await {
+            // Don't leave rows behind that have no content
+            for metadata in &stored[index..] {
+                sqlx::query("DELETE FROM files WHERE id = ?").bind(metadata.id).execute(db).await?;
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// The content of deleted file rows, to give to `release_files`
+pub struct DeletedFiles(Vec<(Option<String>, String)>);
+
+//DELETE THE ROWS OF EVERY FILE OF A POST
+//
+// Their content is only released by `release_files`, after the transaction
+// the rows were deleted in has committed
+pub async fn delete_file_rows(conn: &mut SqliteConnection, post_id: i64) -> Result<DeletedFiles, ApiError> {
+    let files: Vec<(Option<String>, String)> = sqlx::query_as("SELECT sha256, storage FROM files WHERE post_id = ?")
+        .bind(post_id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM files WHERE post_id = ?")
+        .bind(post_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(DeletedFiles(files))
+}
+
+// Release content no other row shares
+pub async fn release_files(blobs: &Blobs, deleted: DeletedFiles) -> Result<(), ApiError> {
+    for (sha256, storage) in deleted.0 {
+        blobs.release(&storage, sha256.as_deref()).await?;
+    }
+    Ok(())
+}
+
+//DELETE EVERY FILE OF A POST, INCLUDING CONTENT NO OTHER ROW SHARES
+pub async fn delete_post_files(db: &SqlitePool, blobs: &Blobs, post_id: i64) -> Result<(), ApiError> {
+    let deleted = delete_file_rows(&mut *db.acquire().await?, post_id).await?;
+    release_files(blobs, deleted).await
+}
+
+pub async fn list_files(db: &SqlitePool, post_id: i64) -> Result<Vec<FileMetadata>, ApiError> {
+    let query = "
+        SELECT id, post_id, filename, mime_type, size, sha256, uploaded_at, scan_status, scan_result, storage
+        FROM files
+        WHERE post_id = ?
+        ORDER BY id
+    ";
+
+    Ok(sqlx::query_as(query).bind(post_id).fetch_all(db).await?)
+}
+
+//HASH FILES AND PHOTOS STORED BEFORE CHECKSUMS WERE RECORDED. RUNS ONCE AT STARTUP
+pub async fn backfill_checksums(db: &SqlitePool) -> Result<usize, ApiError> {
+    let mut hashed = backfill_photo_checksums(db).await?;
+
+    loop {
+        let rows: Vec<(i64, Option<Vec<u8>>)> = sqlx::query_as("SELECT id, file FROM files WHERE sha256 IS NULL AND storage = 'sqlite' LIMIT ?")
+            .bind(BACKFILL_BATCH)
+            .fetch_all(db)
+            .await?;
+
+        if rows.is_empty() {
+            return Ok(hashed);
+        }
+
+        for (id, file) in rows {
+            let data = file.unwrap_or_default();
+            sqlx::query("UPDATE files SET sha256 = ?, size = ? WHERE id = ?")
+                .bind(sha256_hex(&data))
+                .bind(data.len() as i64)
+                .bind(id)
+                .execute(db)
+                .await?;
+            hashed += 1;
+        }
+    }
+}
+
+async fn backfill_photo_checksums(db: &SqlitePool) -> Result<usize, ApiError> {
+    let mut hashed = 0;
+
+    loop {
+        let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as("SELECT id, photo FROM authors WHERE photo IS NOT NULL AND photo_sha256 IS NULL LIMIT ?")
+            .bind(BACKFILL_BATCH)
+            .fetch_all(db)
+            .await?;
+
+        if rows.is_empty() {
+            return Ok(hashed);
+        }
+
+        for (id, photo) in rows {
+            sqlx::query("UPDATE authors SET photo_sha256 = ? WHERE id = ?")
+                .bind(sha256_hex(&photo))
+                .bind(id)
+                .execute(db)
+                .await?;
+            hashed += 1;
+        }
+    }
+}
+
+pub async fn get_file_metadata(db: &SqlitePool, file_id: i64) -> Result<FileMetadata, ApiError> {
+    let query = "
+        SELECT id, post_id, filename, mime_type, size, sha256, uploaded_at, scan_status, scan_result, storage
+        FROM files
+        WHERE id = ?
+    ";
+
+    sqlx::query_as(query)
+        .bind(file_id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("File not found".to_string()))
+}
+
+//READ length BYTES OF A FILE STARTING AT start, A CHUNK AT A TIME
+//
+// Stores that can seek are read where each chunk starts. From the others
+// the whole range is read on the first chunk and handed out from memory,
+// instead of reading the blob again for every chunk
+pub struct FileReader<'a> {
+    store: &'a dyn BlobStore,
+    owner: BlobOwner,
+    sha256: &'a str,
+    offset: u64,
+    end: u64,
+    buffered: Option<Bytes>,
+}
+
+impl<'a> FileReader<'a> {
+    pub fn new(blobs: &'a Blobs, file: &'a FileMetadata, start: u64, length: u64) -> Result<FileReader<'a>, ApiError> {
+        let store = blobs.store(&file.storage)?;
+        Ok(FileReader::from_store(store, BlobOwner::File(file.id), file.sha256.as_deref().unwrap_or_default(), start, length))
+    }
+
+    pub fn from_store(store: &'a dyn BlobStore, owner: BlobOwner, sha256: &'a str, start: u64, length: u64) -> FileReader<'a> {
+        FileReader { store, owner, sha256, offset: start, end: start.saturating_add(length), buffered: None }
+    }
+
+    // Up to `size` bytes following the previous chunk, empty once the range or the blob ends
+    pub async fn next_chunk(&mut self, size: u64) -> Result<Bytes, ApiError> {
+        let size = size.min(self.end - self.offset);
+        if size == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let chunk = if self.store.seeks() {
+            Bytes::from(self.store.read(self.owner, self.sha256, self.offset, size).await?)
+        } else {
+            if self.buffered.is_none() {
+                let range = self.store.read(self.owner, self.sha256, self.offset, self.end - self.offset).await?;
+                self.buffered = Some(Bytes::from(range));
+            }
+            let buffered = self.buffered.as_mut().expect("filled above");
+            buffered.split_to((size as usize).min(buffered.len()))
+        };
+        self.offset += chunk.len() as u64;
+        Ok(chunk)
+    }
+}
+
+//SCAN FILES STORED WHILE NO SCANNER WAS CONFIGURED, RETURNS HOW MANY
+//
+// Runs in the background after startup. Stops at the first scan that fails,
+// the rest are picked up on the next start
+pub async fn scan_unscanned_files(db: &SqlitePool, blobs: &Blobs) -> Result<usize, ApiError> {
+    let scanner = blobs.scanner();
+    let mut scanned = 0;
+    let mut last_id = 0;
+
+    loop {
+        let query = "
+            SELECT id, post_id, filename, mime_type, size, sha256, uploaded_at, scan_status, scan_result, storage
+            FROM files
+            WHERE scan_status = ? AND id > ?
+            ORDER BY id
+            LIMIT ?
+        ";
+        let files: Vec<FileMetadata> = sqlx::query_as(query)
+            .bind(ScanStatus::Unscanned.as_str())
+            .bind(last_id)
+            .bind(BACKFILL_BATCH)
+            .fetch_all(db)
+            .await?;
+
+        if files.is_empty() {
+            return Ok(scanned);
+        }
+
+        for file in files {
+            last_id = file.id;
+            let data = blobs.read_all(&file.storage, BlobOwner::File(file.id), file.sha256.as_deref().unwrap_or_default()).await?;
+            let report = scanner.scan(&data).await?;
+            if report.status == ScanStatus::Quarantined {
+                println!("Quarantined file {}: {}", file.id, report.result.as_deref().unwrap_or_default());
+            }
+
+            sqlx::query("UPDATE files SET scan_status = ?, scan_result = ? WHERE id = ?")
+                .bind(report.status.as_str())
+                .bind(&report.result)
+                .bind(file.id)
+                .execute(db)
+                .await?;
+            scanned += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    // Holds one blob in memory and counts how often it is read
+    struct CountingStore {
+        data: Vec<u8>,
+        seeks: bool,
+        reads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BlobStore for CountingStore {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn seeks(&self) -> bool {
+            self.seeks
+        }
+
+        async fn put(&self, _owner: BlobOwner, _sha256: &str, _data: &[u8]) -> Result<(), ApiError> {
+            Ok(())
+        }
+
+        async fn read(&self, _owner: BlobOwner, _sha256: &str, offset: u64, length: u64) -> Result<Vec<u8>, ApiError> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let start = (offset as usize).min(self.data.len());
+            let end = start.saturating_add(length as usize).min(self.data.len());
+            Ok(self.data[start..end].to_vec())
+        }
+
+        async fn release(&self, _sha256: &str) -> Result<(), ApiError> {
+            Ok(())
+        }
+    }
+
+    const CHUNK: u64 = 64 * 1024;
+
+    async fn read_in_chunks(store: &CountingStore, start: u64, length: u64) -> Vec<u8> {
+        let mut reader = FileReader::from_store(store, BlobOwner::File(1), "", start, length);
+        let mut read = Vec::new();
+        loop {
+            let chunk = reader.next_chunk(CHUNK).await.unwrap();
+            if chunk.is_empty() {
+                return read;
+            }
+            assert!(chunk.len() as u64 <= CHUNK);
+            read.extend_from_slice(&chunk);
+        }
+    }
+
+    fn counting_store(seeks: bool) -> CountingStore {
+        let data = (0..CHUNK as usize * 3 + 17).map(|i| (i % 251) as u8).collect();
+        CountingStore { data, seeks, reads: AtomicUsize::new(0) }
+    }
+
+    #[tokio::test]
+    async fn reads_a_blob_that_cant_seek_once() {
+        let store = counting_store(false);
+        assert_eq!(read_in_chunks(&store, 0, u64::MAX).await, store.data);
+        assert_eq!(store.reads.load(Ordering::SeqCst), 1);
+
+        let store = counting_store(false);
+        assert_eq!(read_in_chunks(&store, 100, CHUNK * 2).await, store.data[100..100 + CHUNK as usize * 2]);
+        assert_eq!(store.reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_a_blob_that_seeks_a_chunk_at_a_time() {
+        let store = counting_store(true);
+        assert_eq!(read_in_chunks(&store, 0, store.data.len() as u64).await, store.data);
+        assert_eq!(store.reads.load(Ordering::SeqCst), 4);
+    }
+}
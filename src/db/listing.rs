@@ -0,0 +1,434 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use warp::http::header::{HeaderValue, LINK};
+use warp::Reply;
+
+use crate::errors::ApiError;
+use crate::models::authors::Author;
+use crate::models::posts::Post;
+use crate::models::response::{AuthorSearchHit, PageInfo, PageQueryParam, PostSearchHit, TotalMode};
+
+pub const DEFAULT_LIMIT: i64 = 10;
+// Hard cap on ?limit=, larger values are clamped
+pub const MAX_LIMIT: i64 = 100;
+const MAX_SORT_KEYS: usize = 4;
+
+// Timestamps are stored the way sqlite's CURRENT_TIMESTAMP writes them, so
+// filters are normalised to the same text form and compared as strings
+const DB_TIMESTAMP: &str = "%Y-%m-%d %H:%M:%S";
+
+// Which columns a list endpoint exposes. Only names in these lists ever reach
+// the SQL text, everything else the client sends is bound as a parameter
+pub struct Resource {
+    pub name: &'static str,
+    pub path: &'static str,
+    pub fields: &'static [&'static str],
+    pub sortable: &'static [&'static str],
+    pub filter_by_author: bool,
+}
+
+pub const POSTS: Resource = Resource {
+    name: "posts",
+    path: "/api/posts",
+    fields: &["id", "title", "content", "author_id", "created_at", "updated_at", "created_by"],
+    sortable: &["id", "title", "author_id", "created_at", "updated_at"],
+    filter_by_author: true,
+};
+
+pub const AUTHORS: Resource = Resource {
+    name: "authors",
+    path: "/api/authors",
+    fields: &["id", "name", "surname", "photo_url", "created_at", "updated_at", "created_by"],
+    sortable: &["id", "name", "surname", "created_at", "updated_at"],
+    filter_by_author: false,
+};
+
+// Fields only present on search hits, always kept by sparse fieldsets
+const SEARCH_FIELDS: &[&str] = &["snippet", "score"];
+
+// Rows that can be paged with a (created_at, id) cursor
+pub trait Keyed {
+    fn key(&self) -> (&str, i64);
+}
+
+impl Keyed for Post {
+    fn key(&self) -> (&str, i64) {
+        (&self.created_at, self.id)
+    }
+}
+
+impl Keyed for Author {
+    fn key(&self) -> (&str, i64) {
+        (&self.created_at, self.id)
+    }
+}
+
+impl Keyed for PostSearchHit {
+    fn key(&self) -> (&str, i64) {
+        self.post.key()
+    }
+}
+
+impl Keyed for AuthorSearchHit {
+    fn key(&self) -> (&str, i64) {
+        self.author.key()
+    }
+}
+
+//OPAQUE KEYSET CURSOR: BASE64URL OF [created_at, id]
+pub struct Cursor {
+    created_at: String,
+    id: i64,
+}
+
+impl Cursor {
+    fn encode(created_at: &str, id: i64) -> String {
+        let raw = serde_json::to_vec(&(created_at, id)).expect("a tuple always serializes");
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(param: &str, value: &str) -> Result<Cursor, ApiError> {
+        let invalid = || ApiError::BadRequest(format!("{} is not a valid cursor", param));
+        let raw = URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid())?;
+        let (created_at, id): (String, i64) = serde_json::from_slice(&raw).map_err(|_| invalid())?;
+        Ok(Cursor { created_at, id })
+    }
+}
+
+enum Position {
+    Page(i64),
+    After(Cursor),
+    Before(Cursor),
+}
+
+//VALIDATED LIST OPTIONS FOR ONE REQUEST
+pub struct Listing {
+    path: &'static str,
+    pub limit: i64,
+    position: Position,
+    sort: Vec<(&'static str, bool)>,
+    // Some(descending) when the order is (created_at, id), so cursors work
+    keyset: Option<bool>,
+    fields: Option<Vec<&'static str>>,
+    author_id: Option<i64>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+    total: Option<TotalMode>,
+    // Everything but the position, repeated in the Link header URLs
+    link_params: Vec<(&'static str, String)>,
+}
+
+impl Listing {
+    pub fn parse(resource: &Resource, params: &PageQueryParam, search: Option<&str>) -> Result<Listing, ApiError> {
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit < 1 {
+            return Err(ApiError::BadRequest("limit must be at least 1".to_string()));
+        }
+        let limit = limit.min(MAX_LIMIT);
+
+        if params.author_id.is_some() && !resource.filter_by_author {
+            return Err(ApiError::BadRequest(format!("{} can not be filtered by author_id", resource.name)));
+        }
+
+        let sort = parse_sort(resource, params.sort.as_deref())?;
+        let keyset = match sort.as_slice() {
+            [] if search.is_none() => Some(false),
+            [("created_at", descending)] => Some(*descending),
+            [("created_at", descending), ("id", id_descending)] if descending == id_descending => Some(*descending),
+            _ => None,
+        };
+
+        let position = match (params.page, &params.after, &params.before) {
+            (None, None, None) => Position::Page(0),
+            (Some(page), None, None) => Position::Page((i64::from(page.max(1)) - 1) * limit),
+            (None, Some(after), None) => Position::After(Cursor::decode("after", after)?),
+            (None, None, Some(before)) => Position::Before(Cursor::decode("before", before)?),
+            _ => return Err(ApiError::BadRequest("Use only one of page, after and before".to_string())),
+        };
+        if keyset.is_none() && !matches!(position, Position::Page(_)) {
+            return Err(ApiError::BadRequest(
+                "Cursors need the default order or sort=created_at / sort=-created_at".to_string(),
+            ));
+        }
+
+        let mut link_params = vec![("limit", limit.to_string())];
+        if let Some(q) = search {
+            link_params.push(("q", q.to_string()));
+        }
+        let optional = [
+            ("sort", params.sort.clone()),
+            ("fields", params.fields.clone()),
+            ("author_id", params.author_id.map(|id| id.to_string())),
+            ("created_after", params.created_after.clone()),
+            ("created_before", params.created_before.clone()),
+            ("total", params.total.map(|mode| mode.as_str().to_string())),
+        ];
+        link_params.extend(optional.into_iter().filter_map(|(name, value)| value.map(|value| (name, value))));
+
+        Ok(Listing {
+            path: resource.path,
+            limit,
+            position,
+            sort,
+            keyset,
+            fields: parse_fields(resource, params.fields.as_deref())?,
+            author_id: params.author_id,
+            created_after: params.created_after.as_deref().map(|v| parse_timestamp("created_after", v)).transpose()?,
+            created_before: params.created_before.as_deref().map(|v| parse_timestamp("created_before", v)).transpose()?,
+            total: params.total,
+            link_params,
+        })
+    }
+
+    // Appends "AND ..." conditions, the builder must already hold a WHERE clause
+    pub fn push_filters(&self, query: &mut QueryBuilder<'_, Sqlite>, alias: &str) {
+        if let Some(author_id) = self.author_id {
+            query.push(format!(" AND {}.author_id = ", alias)).push_bind(author_id);
+        }
+        if let Some(created_after) = &self.created_after {
+            query.push(format!(" AND {}.created_at > ", alias)).push_bind(created_after.clone());
+        }
+        if let Some(created_before) = &self.created_before {
+            query.push(format!(" AND {}.created_at < ", alias)).push_bind(created_before.clone());
+        }
+    }
+
+    // Keyset condition for ?after= / ?before=, a no-op when paging by offset
+    pub fn push_cursor(&self, query: &mut QueryBuilder<'_, Sqlite>, alias: &str) {
+        let descending = self.keyset.unwrap_or(false);
+        let (cursor, forward) = match &self.position {
+            Position::Page(_) => return,
+            Position::After(cursor) => (cursor, true),
+            Position::Before(cursor) => (cursor, false),
+        };
+        let operator = if forward != descending { ">" } else { "<" };
+
+        query
+            .push(format!(" AND ({0}.created_at, {0}.id) {1} (", alias, operator))
+            .push_bind(cursor.created_at.clone())
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+
+    // Requested order, falling back to `default` and then to (created_at, id).
+    // id always breaks ties so pages are stable. ?before= walks backwards and
+    // `page` restores the order afterwards
+    pub fn push_order(&self, query: &mut QueryBuilder<'_, Sqlite>, alias: &str, default: Option<&str>) {
+        let flip = matches!(self.position, Position::Before(_));
+        let direction = |descending: bool| if descending != flip { "DESC" } else { "ASC" };
+
+        let mut keys: Vec<String> = self
+            .sort
+            .iter()
+            .map(|(column, descending)| format!("{}.{} {}", alias, column, direction(*descending)))
+            .collect();
+        if keys.is_empty() {
+            match default {
+                Some(default) => keys.push(default.to_string()),
+                None => keys.push(format!("{}.created_at {}", alias, direction(false))),
+            }
+        }
+        if !self.sort.iter().any(|(column, _)| *column == "id") {
+            let descending = self.sort.first().map(|(_, descending)| *descending).unwrap_or(false);
+            keys.push(format!("{}.id {}", alias, direction(descending)));
+        }
+        query.push(" ORDER BY ").push(keys.join(", "));
+    }
+
+    // One row more than requested tells whether another page follows
+    pub fn push_limit(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        query.push(" LIMIT ").push_bind(self.limit + 1);
+        if let Position::Page(offset) = self.position {
+            query.push(" OFFSET ").push_bind(offset);
+        }
+    }
+
+    //COUNT MATCHING ROWS, OR ESTIMATE THE SIZE OF THE WHOLE TABLE CHEAPLY
+    //
+    // `count` is the list query's FROM/WHERE with filters applied, selecting COUNT(*).
+    // The estimate ignores filters and reads the highest id, so deletes make it high
+    pub async fn total(&self, db: &SqlitePool, resource: &Resource, mut count: QueryBuilder<'_, Sqlite>) -> Result<Option<i64>, ApiError> {
+        match self.total {
+            None => Ok(None),
+            Some(TotalMode::Exact) => {
+                let (total,): (i64,) = count.build_query_as().fetch_one(db).await?;
+                Ok(Some(total))
+            }
+            Some(TotalMode::Estimate) => {
+                let query = format!("SELECT COALESCE(MAX(id), 0) FROM {}", resource.name);
+                let (total,): (i64,) = sqlx::query_as(&query).fetch_one(db).await?;
+                Ok(Some(total))
+            }
+        }
+    }
+
+    //TRIM THE LOOKAHEAD ROW AND WORK OUT WHERE THE NEIGHBOURING PAGES ARE
+    pub fn page<T: Keyed>(&self, mut rows: Vec<T>, total: Option<i64>) -> (Vec<T>, PageInfo) {
+        let has_more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let (has_next, has_prev) = match self.position {
+            Position::Page(offset) => (has_more, offset > 0),
+            Position::After(_) => (has_more, true),
+            Position::Before(_) => {
+                rows.reverse();
+                (true, has_more)
+            }
+        };
+
+        let cursor = |row: Option<&T>| {
+            row.filter(|_| self.keyset.is_some()).map(|row| {
+                let (created_at, id) = row.key();
+                Cursor::encode(created_at, id)
+            })
+        };
+
+        let info = PageInfo {
+            limit: self.limit,
+            next: if has_next { cursor(rows.last()) } else { None },
+            prev: if has_prev { cursor(rows.first()) } else { None },
+            total,
+            total_estimated: self.total == Some(TotalMode::Estimate),
+            has_next,
+            has_prev,
+        };
+        (rows, info)
+    }
+
+    //SERIALIZE A LIST RESPONSE, KEEPING ONLY THE REQUESTED FIELDS OF EACH ITEM, AND ADD LINK HEADERS
+    pub fn reply<T: Serialize>(&self, response: &T, list_key: &str, info: &PageInfo) -> Result<warp::reply::Response, ApiError> {
+        let mut reply = match &self.fields {
+            None => warp::reply::json(response).into_response(),
+            Some(fields) => {
+                let mut body = serde_json::to_value(response).map_err(|e| ApiError::Internal(e.to_string()))?;
+                if let Some(Value::Array(items)) = body.get_mut(list_key) {
+                    for item in items.iter_mut() {
+                        if let Value::Object(map) = item {
+                            map.retain(|key, _| fields.contains(&key.as_str()) || SEARCH_FIELDS.contains(&key.as_str()));
+                        }
+                    }
+                }
+                warp::reply::json(&body).into_response()
+            }
+        };
+
+        let links = self.links(info)?;
+        if !links.is_empty() {
+            let value = HeaderValue::from_str(&links.join(", ")).map_err(|e| ApiError::Internal(e.to_string()))?;
+            reply.headers_mut().insert(LINK, value);
+        }
+        Ok(reply)
+    }
+
+    // RFC 8288 links. Cursors when the order allows them, page numbers otherwise
+    fn links(&self, info: &PageInfo) -> Result<Vec<String>, ApiError> {
+        let mut links = vec![self.link("first", None)?];
+
+        match (&self.position, self.keyset) {
+            (_, Some(_)) => {
+                if let Some(next) = &info.next {
+                    links.push(self.link("next", Some(("after", next.clone())))?);
+                }
+                if let Some(prev) = &info.prev {
+                    links.push(self.link("prev", Some(("before", prev.clone())))?);
+                }
+            }
+            (Position::Page(offset), None) => {
+                let page = offset / self.limit + 1;
+                if info.has_next {
+                    links.push(self.link("next", Some(("page", (page + 1).to_string())))?);
+                }
+                if info.has_prev {
+                    links.push(self.link("prev", Some(("page", (page - 1).to_string())))?);
+                }
+            }
+            _ => {}
+        }
+        Ok(links)
+    }
+
+    fn link(&self, rel: &str, position: Option<(&'static str, String)>) -> Result<String, ApiError> {
+        let mut params = self.link_params.clone();
+        params.extend(position);
+        let query = serde_urlencoded::to_string(&params).map_err(|e| ApiError::Internal(e.to_string()))?;
+        Ok(format!("<{}?{}>; rel=\"{}\"", self.path, query, rel))
+    }
+}
+
+// "-created_at,title" -> [(created_at, DESC), (title, ASC)]
+fn parse_sort(resource: &Resource, sort: Option<&str>) -> Result<Vec<(&'static str, bool)>, ApiError> {
+    let mut keys = Vec::new();
+    for key in sort.unwrap_or("").split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        let (name, descending) = match key.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (key.strip_prefix('+').unwrap_or(key), false),
+        };
+        let column = whitelisted(resource.sortable, name).ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "Can not sort {} by {:?}, allowed: {}",
+                resource.name,
+                name,
+                resource.sortable.join(", ")
+            ))
+        })?;
+        if keys.iter().any(|(existing, _)| *existing == column) {
+            return Err(ApiError::BadRequest(format!("Sort key {:?} is given more than once", column)));
+        }
+        keys.push((column, descending));
+    }
+
+    if keys.len() > MAX_SORT_KEYS {
+        return Err(ApiError::BadRequest(format!("At most {} sort keys are allowed", MAX_SORT_KEYS)));
+    }
+    Ok(keys)
+}
+
+fn parse_fields(resource: &Resource, fields: Option<&str>) -> Result<Option<Vec<&'static str>>, ApiError> {
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok(None),
+    };
+
+    let mut selected = Vec::new();
+    for name in fields.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let field = whitelisted(resource.fields, name).ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "Unknown field {:?} for {}, allowed: {}",
+                name,
+                resource.name,
+                resource.fields.join(", ")
+            ))
+        })?;
+        selected.push(field);
+    }
+
+    if selected.is_empty() {
+        return Err(ApiError::BadRequest("fields must name at least one field".to_string()));
+    }
+    Ok(Some(selected))
+}
+
+fn whitelisted(allowed: &'static [&'static str], name: &str) -> Option<&'static str> {
+    allowed.iter().copied().find(|column| *column == name)
+}
+
+// Accepts RFC 3339, "YYYY-MM-DD HH:MM:SS" or a plain date (midnight)
+fn parse_timestamp(param: &str, value: &str) -> Result<String, ApiError> {
+    let parsed = DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, DB_TIMESTAMP))
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).expect("midnight is valid")))
+        .map_err(|_| {
+            ApiError::BadRequest(format!(
+                "{} must be a date (2024-01-31) or timestamp (2024-01-31T12:00:00Z)",
+                param
+            ))
+        })?;
+
+    Ok(parsed.format(DB_TIMESTAMP).to_string())
+}
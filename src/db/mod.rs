@@ -1 +1,59 @@
-pub mod database;
\ No newline at end of file
+pub mod database;
+pub mod events;
+pub mod files;
+pub mod listing;
+pub mod quotas;
+pub mod search;
+pub mod uploads;
+
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+
+use crate::errors::ApiError;
+
+//A TRANSACTION THAT HOLDS THE DATABASE'S WRITE LOCK FROM ITS FIRST STATEMENT
+//
+// `begin()` only takes the lock at the first write, so two requests could
+// both read the same totals before either of them writes. Rolled back when
+// dropped without `commit`
+pub struct ImmediateTx {
+    conn: Option<PoolConnection<Sqlite>>,
+}
+
+impl ImmediateTx {
+    pub async fn begin(db: &SqlitePool) -> Result<ImmediateTx, ApiError> {
+        let mut conn = db.acquire().await?;
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+        Ok(ImmediateTx { conn: Some(conn) })
+    }
+
+    pub fn conn(&mut self) -> &mut SqliteConnection {
+        self.conn.as_mut().expect("the connection is only taken by commit")
+    }
+
+    pub async fn commit(mut self) -> Result<(), ApiError> {
+        sqlx::query("COMMIT").execute(self.conn()).await?;
+        // Back to the pool, with nothing left open
+        self.conn.take();
+        Ok(())
+    }
+}
+
+impl Drop for ImmediateTx {
+    fn drop(&mut self) {
+        // Closing the connection instead of pooling it rolls the transaction back
+        if let Some(conn) = self.conn.take() {
+            drop(conn.detach());
+        }
+    }
+}
+
+// A fresh in-memory database with every migration applied. One connection,
+// since each connection to :memory: is its own database
+#[cfg(test)]
+pub async fn memory_db() -> sqlx::SqlitePool {
+    let db = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+    let migrations = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    sqlx::migrate::Migrator::new(migrations).await.unwrap().run(&db).await.unwrap();
+    db
+}
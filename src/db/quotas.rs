@@ -0,0 +1,57 @@
+use sqlx::SqliteConnection;
+
+use crate::config::QuotaConfig;
+use crate::errors::ApiError;
+use crate::models::response::StorageUsage;
+use crate::Middleware::auth::CurrentUser;
+
+//WHAT A USER STORES AND MAY STILL STORE
+pub async fn storage_usage(conn: &mut SqliteConnection, quotas: &QuotaConfig, user: &CurrentUser) -> Result<StorageUsage, ApiError> {
+    let (used_bytes, files): (i64, i64) =
+        sqlx::query_as("SELECT COALESCE(SUM(size), 0), COUNT(*) FROM files WHERE uploaded_by = ?")
+            .bind(user.id)
+            .fetch_one(&mut *conn)
+            .await?;
+    let (pending_bytes,): (i64,) = sqlx::query_as("SELECT COALESCE(SUM(length), 0) FROM uploads WHERE user_id = ?")
+        .bind(user.id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let (own_quota,): (Option<i64>,) = sqlx::query_as("SELECT storage_quota FROM users WHERE id = ?")
+        .bind(user.id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let quota_bytes = own_quota.or_else(|| quotas.for_role(user.role()));
+    Ok(StorageUsage {
+        used_bytes,
+        files,
+        pending_bytes,
+        quota_bytes,
+        remaining_bytes: quota_bytes.map(|quota| (quota - used_bytes - pending_bytes).max(0)),
+    })
+}
+
+//REFUSE TO STORE `incoming` MORE BYTES WHEN THEY DON'T FIT IN THE USER'S QUOTA
+//
+// `freed` is what the same change gives back: files it replaces and the
+// reservations of resumable uploads it attaches. Checked in the same
+// ImmediateTx as the rows it allows, nothing else can store in between
+pub async fn ensure_quota(
+    conn: &mut SqliteConnection,
+    quotas: &QuotaConfig,
+    user: &CurrentUser,
+    incoming: i64,
+    freed: i64,
+) -> Result<(), ApiError> {
+    let usage = storage_usage(conn, quotas, user).await?;
+    let quota = match usage.quota_bytes {
+        Some(quota) => quota,
+        None => return Ok(()),
+    };
+
+    let used = usage.used_bytes + usage.pending_bytes - freed;
+    if used + incoming > quota {
+        return Err(ApiError::QuotaExceeded { quota, used: used.max(0), requested: incoming });
+    }
+    Ok(())
+}
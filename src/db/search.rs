@@ -0,0 +1,110 @@
+use crate::errors::ApiError;
+
+// Upper bound on terms per query, keeps pathological inputs cheap
+const MAX_TERMS: usize = 16;
+
+// FTS5 marks matches with these private use characters (U+E000, U+E001),
+// highlight_html turns them into <mark> once the text around them is escaped
+const MARK_START: char = '\u{E000}';
+const MARK_END: char = '\u{E001}';
+pub const POST_SNIPPET: &str = "snippet(posts_fts, -1, char(57344), char(57345), '…', 16)";
+pub const AUTHOR_SNIPPET: &str = "highlight(authors_fts, 0, char(57344), char(57345)) || ' ' || highlight(authors_fts, 1, char(57344), char(57345))";
+
+//TURN FREE TEXT FROM ?q= INTO A SAFE FTS5 MATCH EXPRESSION
+//
+// "quoted text" becomes a phrase, word* becomes a prefix search and every
+// other word is matched as is. Everything is emitted inside double quotes,
+// so FTS5 operators and column filters in the input are never interpreted.
+pub fn fts_query(input: &str) -> Result<String, ApiError> {
+    let mut terms = Vec::new();
+
+    for (i, part) in input.split('"').enumerate() {
+        // Odd parts sit between a pair of quotes (or after an unterminated one)
+        if i % 2 == 1 {
+            let words: Vec<&str> = part.split_whitespace().filter(|word| is_searchable(word)).collect();
+            if !words.is_empty() {
+                terms.push(format!("\"{}\"", words.join(" ")));
+            }
+            continue;
+        }
+
+        for word in part.split_whitespace() {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(stem) => (stem.trim_end_matches('*'), true),
+                None => (word, false),
+            };
+            if !is_searchable(word) {
+                continue;
+            }
+            if prefix {
+                terms.push(format!("\"{}\"*", word));
+            } else {
+                terms.push(format!("\"{}\"", word));
+            }
+        }
+    }
+
+    if terms.is_empty() {
+        return Err(ApiError::BadRequest("Search query must contain at least one word".to_string()));
+    }
+    if terms.len() > MAX_TERMS {
+        return Err(ApiError::BadRequest(format!("Search query must not contain more than {} terms", MAX_TERMS)));
+    }
+
+    Ok(terms.join(" "))
+}
+
+// Words without a letter or digit would tokenize to nothing
+fn is_searchable(word: &str) -> bool {
+    word.chars().any(char::is_alphanumeric)
+}
+
+//ESCAPE A SNIPPET AS HTML, WITH ITS MATCHES IN <mark></mark>
+//
+// Snippets are user content, only the marks may come out as tags
+pub fn highlight_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len() + 16);
+    for c in snippet.chars() {
+        match c {
+            MARK_START => html.push_str("<mark>"),
+            MARK_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#x27;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory_db;
+
+    #[test]
+    fn highlight_html_escapes_everything_but_the_marks() {
+        let snippet = format!("<b>{}x</b>{} & \"y\" 'z'", MARK_START, MARK_END);
+        assert_eq!(highlight_html(&snippet), "&lt;b&gt;<mark>x&lt;/b&gt;</mark> &amp; &quot;y&quot; &#x27;z&#x27;");
+    }
+
+    #[tokio::test]
+    async fn post_snippet_of_a_script_is_escaped() {
+        let db = memory_db().await;
+
+        sqlx::query("INSERT INTO authors (id, name, surname) VALUES (1, 'Ann', 'Lee')").execute(&db).await.unwrap();
+        sqlx::query("INSERT INTO posts (title, content, author_id) VALUES ('Hello', '<script>alert(1)</script> rust', 1)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let query = format!("SELECT {} FROM posts_fts WHERE posts_fts MATCH ?", POST_SNIPPET);
+        let snippet: String = sqlx::query_scalar(&query).bind(fts_query("alert").unwrap()).fetch_one(&db).await.unwrap();
+
+        let html = highlight_html(&snippet);
+        assert!(!html.contains("<script>"), "{}", html);
+        assert!(html.contains("&lt;script&gt;<mark>alert</mark>(1)&lt;/script&gt;"), "{}", html);
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+
+use crate::db::files::NewFile;
+use crate::errors::ApiError;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// A resumable upload, without its bytes
+#[derive(Debug, Clone, FromRow)]
+pub struct Upload {
+    pub id: String,
+    pub user_id: i64,
+    pub filename: Option<String>,
+    pub metadata: Option<String>,
+    pub length: i64,
+    pub received: i64,
+    // UTC
+    pub expires_at: String,
+}
+
+impl Upload {
+    pub fn is_complete(&self) -> bool {
+        self.received == self.length
+    }
+
+    pub fn expires_at(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(&self.expires_at, TIMESTAMP_FORMAT).ok()
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at().is_none_or(|expires_at| expires_at <= Utc::now().naive_utc())
+    }
+}
+
+fn expiry(hours: i64) -> String {
+    (Utc::now() + Duration::hours(hours)).format(TIMESTAMP_FORMAT).to_string()
+}
+
+pub async fn create_upload(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    filename: Option<String>,
+    metadata: Option<String>,
+    length: i64,
+    expiry_hours: i64,
+) -> Result<Upload, ApiError> {
+    let upload = Upload {
+        id: uuid::Uuid::new_v4().to_simple().to_string(),
+        user_id,
+        filename,
+        metadata,
+        length,
+        received: 0,
+        expires_at: expiry(expiry_hours),
+    };
+
+    sqlx::query("INSERT INTO uploads (id, user_id, filename, metadata, length, expires_at) VALUES (?, ?, ?, ?, ?, ?)")
+        .bind(&upload.id)
+        .bind(upload.user_id)
+        .bind(&upload.filename)
+        .bind(&upload.metadata)
+        .bind(upload.length)
+        .bind(&upload.expires_at)
+        .execute(conn)
+        .await?;
+
+    Ok(upload)
+}
+
+//AN UPLOAD OF THE GIVEN USER. EXPIRED ONES ARE DELETED ON THE WAY AND ARE 410
+pub async fn get_upload(db: &SqlitePool, id: &str, user_id: i64) -> Result<Upload, ApiError> {
+    let upload: Upload = sqlx::query_as(
+        "SELECT id, user_id, filename, metadata, length, received, expires_at FROM uploads WHERE id = ? AND user_id = ?",
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Upload not found".to_string()))?;
+
+    if upload.is_expired() {
+        delete_upload(db, id).await?;
+        return Err(ApiError::Gone("Upload has expired".to_string()));
+    }
+    Ok(upload)
+}
+
+//ADD THE NEXT PIECE OF AN UPLOAD, WHICH MUST START WHERE THE LAST ONE ENDED
+//
+// The offset is checked again inside the transaction, so of two requests
+// racing for the same offset only one gets in. Each piece pushes the
+// expiration back
+pub async fn append_chunk(db: &SqlitePool, upload: &mut Upload, data: &[u8], expiry_hours: i64) -> Result<(), ApiError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let expires_at = expiry(expiry_hours);
+    let mut tx = db.begin().await?;
+
+    let updated = sqlx::query("UPDATE uploads SET received = received + ?, expires_at = ? WHERE id = ? AND received = ?")
+        .bind(data.len() as i64)
+        .bind(&expires_at)
+        .bind(&upload.id)
+        .bind(upload.received)
+        .execute(&mut *tx)
+        .await?;
+    if updated.rows_affected() == 0 {
+        return Err(ApiError::Conflict("Upload-Offset no longer matches the upload".to_string()));
+    }
+
+    sqlx::query("INSERT INTO upload_chunks (upload_id, start, data) VALUES (?, ?, ?)")
+        .bind(&upload.id)
+        .bind(upload.received)
+        .bind(data)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    upload.received += data.len() as i64;
+    upload.expires_at = expires_at;
+    Ok(())
+}
+
+pub async fn delete_upload(db: &SqlitePool, id: &str) -> Result<(), ApiError> {
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM upload_chunks WHERE upload_id = ?").bind(id).execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM uploads WHERE id = ?").bind(id).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+//DELETE EVERY UPLOAD PAST ITS EXPIRATION, RETURNS HOW MANY
+pub async fn prune_expired_uploads(db: &SqlitePool) -> Result<u64, ApiError> {
+    let now = Utc::now().format(TIMESTAMP_FORMAT).to_string();
+
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM upload_chunks WHERE upload_id IN (SELECT id FROM uploads WHERE expires_at <= ?)")
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM uploads WHERE expires_at <= ?").bind(&now).execute(&mut *tx).await?;
+    tx.commit().await?;
+
+    Ok(deleted.rows_affected())
+}
+
+//TAKE FINISHED UPLOADS OUT OF THE TABLE, IN THE TRANSACTION THAT ADDS THEIR FILE ROWS
+//
+// Of two requests attaching the same upload only the first one gets it, the
+// other one fails and its transaction is rolled back
+pub async fn claim_uploads(conn: &mut SqliteConnection, user_id: i64, ids: &[String]) -> Result<(), ApiError> {
+    for id in ids {
+        let claimed = sqlx::query("DELETE FROM uploads WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?;
+        if claimed.rows_affected() != 1 {
+            return Err(ApiError::Validation(format!("Upload {} does not exist or was already attached", id)));
+        }
+        sqlx::query("DELETE FROM upload_chunks WHERE upload_id = ?").bind(id).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+//THE FILES OF FINISHED UPLOADS, READY TO BE ATTACHED TO A POST
+//
+// Every id has to be a complete upload of the caller. The uploads stay until
+// `claim_uploads` takes them along with the file rows
+pub async fn completed_uploads(db: &SqlitePool, user_id: i64, ids: &[String]) -> Result<Vec<NewFile>, ApiError> {
+    let mut files = Vec::new();
+
+    for id in ids {
+        let upload = match get_upload(db, id, user_id).await {
+            Ok(upload) => upload,
+            Err(ApiError::NotFound(_)) | Err(ApiError::Gone(_)) => {
+                return Err(ApiError::Validation(format!("Upload {} does not exist or has expired", id)));
+            }
+            Err(e) => return Err(e),
+        };
+        if !upload.is_complete() {
+            return Err(ApiError::Validation(format!(
+                "Upload {} is not complete, {} of {} bytes received",
+                id, upload.received, upload.length
+            )));
+        }
+
+        let chunks: Vec<(Vec<u8>,)> = sqlx::query_as("SELECT data FROM upload_chunks WHERE upload_id = ? ORDER BY start")
+            .bind(id)
+            .fetch_all(db)
+            .await?;
+        let mut data = Vec::with_capacity(upload.length as usize);
+        for (chunk,) in chunks {
+            data.extend_from_slice(&chunk);
+        }
+        if data.len() as i64 != upload.length {
+            return Err(ApiError::Internal(format!("Upload {} has {} bytes stored, expected {}", id, data.len(), upload.length)));
+        }
+
+        files.push(NewFile::new(upload.filename.as_deref(), data));
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory_db;
+
+    #[tokio::test]
+    async fn an_upload_is_claimed_once() {
+        let db = memory_db().await;
+        sqlx::query("INSERT INTO users (id, username, password) VALUES (1, 'ann', 'x'), (2, 'bob', 'x')").execute(&db).await.unwrap();
+        let upload = create_upload(&mut db.acquire().await.unwrap(), 1, None, None, 5, 24).await.unwrap();
+        let ids = vec![upload.id];
+
+        let mut conn = db.acquire().await.unwrap();
+        assert!(matches!(claim_uploads(&mut conn, 2, &ids).await, Err(ApiError::Validation(_))));
+        claim_uploads(&mut conn, 1, &ids).await.unwrap();
+        assert!(matches!(claim_uploads(&mut conn, 1, &ids).await, Err(ApiError::Validation(_))));
+    }
+}
@@ -0,0 +1,228 @@
+use std::convert::Infallible;
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use warp::http::header::{HeaderValue, CONTENT_RANGE};
+use warp::http::StatusCode;
+use warp::reject::Reject;
+use warp::{Rejection, Reply};
+
+// Every error the API can return. Handlers reject with one of these and
+// `handle_rejection` turns it into an `ErrorBody` with the matching status.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Gone(String),
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    Validation(String),
+    // Carries the full size of the file for the Content-Range header
+    RangeNotSatisfiable(u64),
+    // An upload whose sniffed type isn't on the endpoint's allow-list
+    UnsupportedFileType { filename: Option<String>, detected: String, allowed: Vec<String> },
+    // Storing `requested` more bytes would take the user past their quota.
+    // `used` includes space held by unfinished resumable uploads
+    QuotaExceeded { quota: i64, used: i64, requested: i64 },
+    // The malware scanner flagged an upload that is refused outright
+    MalwareDetected { filename: Option<String>, signature: String },
+    // A service the request depends on, like the malware scanner, is down
+    ServiceUnavailable(String),
+    Database(sqlx::Error),
+    Internal(String),
+}
+
+impl Reject for ApiError {}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => ApiError::NotFound("Record not found".to_string()),
+            sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+                ApiError::Conflict("Record already exists".to_string())
+            }
+            sqlx::Error::Database(db_error) if db_error.is_foreign_key_violation() => {
+                ApiError::Validation("Referenced record does not exist".to_string())
+            }
+            _ => ApiError::Database(error),
+        }
+    }
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) | ApiError::UnsupportedFileType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Validation(_) | ApiError::MalwareDetected { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            ApiError::QuotaExceeded { .. } => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Gone(_) => "gone",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) | ApiError::UnsupportedFileType { .. } => "unsupported_media_type",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::RangeNotSatisfiable(_) => "range_not_satisfiable",
+            ApiError::QuotaExceeded { .. } => "quota_exceeded",
+            ApiError::MalwareDetected { .. } => "malware_detected",
+            ApiError::ServiceUnavailable(_) => "service_unavailable",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Gone(message)
+            | ApiError::PreconditionFailed(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::Validation(message)
+            | ApiError::ServiceUnavailable(message) => message.clone(),
+            ApiError::RangeNotSatisfiable(size) => format!("Requested range is outside the file, which is {} bytes long", size),
+            ApiError::UnsupportedFileType { filename: Some(filename), detected, .. } => {
+                format!("{} is {}, which is not accepted here", filename, detected)
+            }
+            ApiError::UnsupportedFileType { filename: None, detected, .. } => {
+                format!("Files of type {} are not accepted here", detected)
+            }
+            ApiError::QuotaExceeded { quota, used, requested } => format!(
+                "Storage quota exceeded: this needs {} bytes, but only {} of the {} byte quota are left",
+                requested,
+                (quota - used).max(0),
+                quota
+            ),
+            ApiError::MalwareDetected { filename: Some(filename), .. } => {
+                format!("{} was flagged by the malware scanner", filename)
+            }
+            ApiError::MalwareDetected { filename: None, .. } => "The upload was flagged by the malware scanner".to_string(),
+            // Never leak SQL or internal details to clients
+            ApiError::Database(_) | ApiError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::RangeNotSatisfiable(size) => Some(serde_json::json!({ "size": size })),
+            ApiError::QuotaExceeded { quota, used, requested } => Some(serde_json::json!({
+                "quota_bytes": quota,
+                "used_bytes": used,
+                "remaining_bytes": (quota - used).max(0),
+                "requested_bytes": requested,
+            })),
+            ApiError::UnsupportedFileType { filename, detected, allowed } => {
+                Some(serde_json::json!({ "filename": filename, "detected": detected, "allowed": allowed }))
+            }
+            ApiError::MalwareDetected { filename, signature } => {
+                Some(serde_json::json!({ "filename": filename, "signature": signature }))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    #[schema(example = "not_found")]
+    pub code: String,
+    #[schema(example = "Post not found")]
+    pub message: String,
+    pub details: Option<Value>,
+    #[schema(example = "3f1c2b0e9a7d4c5e8f6a1b2c3d4e5f60")]
+    pub request_id: String,
+}
+
+fn error_reply(request_id: String, status: StatusCode, code: &str, message: String, details: Option<Value>) -> warp::reply::Response {
+    let body = ErrorBody {
+        code: code.to_string(),
+        message,
+        details,
+        request_id: request_id.clone(),
+    };
+
+    let reply = warp::reply::with_status(warp::reply::json(&body), status);
+    warp::reply::with_header(reply, "x-request-id", request_id).into_response()
+}
+
+//TURN ANY REJECTION INTO THE JSON ERROR ENVELOPE
+//
+// The request id is in the logs as well, so a client reporting it leads to
+// what went wrong
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let request_id = Uuid::new_v4().to_simple().to_string();
+
+    // Every route checks the Origin first, so a request from a disallowed one
+    // is refused whatever else the routes found wrong with it
+    if let Some(e) = err.find::<warp::filters::cors::CorsForbidden>() {
+        return Ok(error_reply(request_id, StatusCode::FORBIDDEN, "forbidden", e.to_string(), None));
+    }
+
+    if let Some(error) = err.find::<ApiError>() {
+        if let ApiError::Database(db_error) = error {
+            eprintln!("[{}] Database error: {:?}", request_id, db_error);
+        } else if let ApiError::Internal(message) = error {
+            eprintln!("[{}] Internal error: {}", request_id, message);
+        }
+        let mut reply = error_reply(request_id, error.status(), error.code(), error.message(), error.details());
+        if let ApiError::RangeNotSatisfiable(size) = error {
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", size)) {
+                reply.headers_mut().insert(CONTENT_RANGE, value);
+            }
+        }
+        return Ok(reply);
+    }
+
+    let (status, code, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "Resource not found".to_string())
+    } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        (StatusCode::UNPROCESSABLE_ENTITY, "validation_failed", e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::MissingHeader>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if let Some(e) = err.find::<warp::reject::InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "Request body is too large".to_string())
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        (StatusCode::LENGTH_REQUIRED, "length_required", "Content-Length header is required".to_string())
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", "Unsupported content type".to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "Method not allowed".to_string())
+    } else {
+        eprintln!("[{}] Unhandled rejection: {:?}", request_id, err);
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error".to_string())
+    };
+
+    Ok(error_reply(request_id, status, code, message, None))
+}
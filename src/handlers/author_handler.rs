@@ -1,107 +1,226 @@
-use std::io;
-use crate::ws::ws_handler::send_message_to_clients;
+use crate::ws::events::{Event, EventData};
+use crate::ws::ws_handler::send_event_to_clients;
 use crate::ws::clients::Clients;
 use base64::decode;
 use crate::models::authors::Author;
-use crate::models::response::{SingeAuthorResponse, AuthorResponse, CreateAuthorRequest, UpdateAuthorRequest, StatusResponse, PageQueryParam};
-use sqlx::SqlitePool;
-
-use warp::reject::Reject;
-use crate::Middleware::mime_check::{check_image_format, check_image_size};
+use crate::models::response::{SingeAuthorResponse, AuthorResponse, CreateAuthorRequest, UpdateAuthorRequest, StatusResponse, PageQueryParam, AuthorSearchHit, AuthorSearchResponse};
+use crate::db::listing::{Listing, AUTHORS};
+use crate::db::search::{fts_query, highlight_html, AUTHOR_SNIPPET};
+use crate::db::files::sha256_hex;
+use crate::storage::{thumbnails, BlobOwner, Blobs};
+use crate::scanner::ScanStatus;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+use crate::Middleware::mime_check::{check_image_size, MAX_IMAGE_SIZE};
+use crate::Middleware::photo::normalize_photo;
+use crate::config::PhotoConfig;
+use crate::handlers::multipart::{next_part, read_part};
+use crate::Middleware::auth::{get_current_user, CurrentUser};
+use crate::errors::ApiError;
+use warp::multipart::FormData;
 use warp::{ Rejection, Reply};
-use serde:: Serialize;
 
 
 
-#[derive(Debug, Serialize)]
-pub struct ErrorResponse {
-    pub message: String,
+//REFUSE A PHOTO THE MALWARE SCANNER FLAGS
+//
+// Unlike post files, photos are shown to everyone, so they are never stored
+// quarantined. The upload as received is scanned, before it is re-encoded
+async fn scan_photo(blobs: &Blobs, photo: &[u8]) -> Result<(), ApiError> {
+    let report = blobs.scanner().scan(photo).await?;
+    match report.status {
+        ScanStatus::Quarantined => Err(ApiError::MalwareDetected { filename: None, signature: report.result.unwrap_or_default() }),
+        ScanStatus::Clean | ScanStatus::Unscanned => Ok(()),
+    }
 }
 
-impl ErrorResponse {
-    pub fn new(message: String) -> Self {
-        ErrorResponse { message }
+//DECODE, SCAN AND RE-ENCODE A BASE64 PHOTO FROM A REQUEST BODY
+async fn decode_photo(blobs: &Blobs, photo: &Option<String>, config: &PhotoConfig) -> Result<Option<Vec<u8>>, ApiError> {
+    let photo = match photo {
+        Some(photo) => photo,
+        None => return Ok(None),
+    };
+
+    let decoded = decode(photo).map_err(|_| ApiError::BadRequest("Photo is not valid base64".to_string()))?;
+
+    if check_image_size(&decoded).is_none() {
+        return Err(ApiError::PayloadTooLarge("Photo must not be larger than 500 kB".to_string()));
     }
+    scan_photo(blobs, &decoded).await?;
+
+    Ok(Some(normalize_photo(decoded, config).await?))
 }
 
-impl warp::reject::Reject for ErrorResponse {}
 
-#[derive(Debug)]
-pub enum FileError {
-    Io(io::Error),
+//STORE OR CLEAR AN AUTHOR'S PHOTO, RELEASING THE ONE IT REPLACES
+async fn store_photo(db: &SqlitePool, blobs: &Blobs, author_id: i64, photo: Option<&[u8]>) -> Result<(), ApiError> {
+    let previous: Option<(String, Option<String>)> = sqlx::query_as("SELECT photo_storage, photo_sha256 FROM authors WHERE id = ?")
+        .bind(author_id)
+        .fetch_optional(db)
+        .await?;
+
+    match photo {
+        Some(photo) => blobs.writer().put(BlobOwner::AuthorPhoto(author_id), &sha256_hex(photo), photo).await?,
+        None => {
+            sqlx::query("UPDATE authors SET photo = NULL, photo_sha256 = NULL, photo_storage = 'sqlite' WHERE id = ?")
+                .bind(author_id)
+                .execute(db)
+                .await?;
+        }
+    }
+
+    if let Some((storage, sha256)) = previous {
+        release_photo(db, blobs, &storage, sha256.as_deref()).await?;
+    }
+    Ok(())
 }
 
-impl warp::reject::Reject for FileError {}
+// An author as stored, for responses and events
+async fn stored_author(db: &SqlitePool, author_id: i64) -> Result<Author, ApiError> {
+    let mut author: Author = sqlx::query_as("SELECT id, name, surname, created_at, updated_at, created_by, photo_sha256 FROM authors WHERE id = ?")
+        .bind(author_id)
+        .fetch_one(db)
+        .await?;
+    author.set_photo_url();
+    Ok(author)
+}
 
-impl From<io::Error> for FileError {
-    fn from(error: io::Error) -> Self {
-        FileError::Io(error)
+//FREE A PHOTO THAT AN AUTHOR NO LONGER USES, ALONG WITH ITS THUMBNAILS
+async fn release_photo(db: &SqlitePool, blobs: &Blobs, storage: &str, sha256: Option<&str>) -> Result<(), ApiError> {
+    blobs.release(storage, sha256).await?;
+    if let Some(sha256) = sha256 {
+        thumbnails::prune(db, sha256).await?;
     }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct MyError(sqlx::Error);
 
-impl Reject for MyError {}
+//CHECK THAT THE CALLER OWNS THE AUTHOR (OR IS AN ADMIN), RETURNS THE OWNER
+async fn authorize_author_change(db: &SqlitePool, author_id: i64, username: &str) -> Result<Option<i64>, Rejection> {
+    let user: CurrentUser = get_current_user(db, username).await?;
 
+    let owner: Option<(Option<i64>,)> = sqlx::query_as("SELECT created_by FROM authors WHERE id = ?")
+        .bind(author_id)
+        .fetch_optional(db)
+        .await
+        .map_err(ApiError::from)?;
 
-//GET ALL AUTHORS
+    match owner {
+        None => Err(ApiError::NotFound("Author not found".to_string()).into()),
+        Some((created_by,)) if user.can_modify(created_by) => Ok(created_by),
+        Some(_) => Err(ApiError::Forbidden("Only the owner or an admin can change this author".to_string()).into()),
+    }
+}
+
+
+//GET ALL AUTHORS, OR SEARCH THEM WITH ?q=
 #[utoipa::path(
     get,
     path = "/api/authors",
     responses(
-        (status = 200, description = "Authors found succescully", body = AuthorResponse),
-        (status = NOT_FOUND, description = "Authors was not found")
+        (status = 200, description = "Authors found succescully, ranked search hits when q is given", body = AuthorResponse),
+        (status = 400, description = "Invalid search, filter, sort or fields parameter", body = ErrorBody),
+        (status = 500, description = "Authors could not be loaded", body = ErrorBody)
     ),
-    
+    params(
+        ("page" = Option<u32>, Query, description = "Page number, starting at 1. Prefer the after/before cursors"),
+        ("limit" = Option<i64>, Query, description = "Authors per page, default 10, at most 100"),
+        ("q" = Option<String>, Query, description = "Full text search over name and surname. Supports \"phrases\" and prefix* terms"),
+        ("sort" = Option<String>, Query, description = "Comma separated sort keys, - for descending: -created_at,surname"),
+        ("fields" = Option<String>, Query, description = "Comma separated fields to return: id,name,surname"),
+        ("created_after" = Option<String>, Query, description = "Only authors created after this date or timestamp"),
+        ("created_before" = Option<String>, Query, description = "Only authors created before this date or timestamp"),
+        ("after" = Option<String>, Query, description = "Cursor from pagination.next, returns the page after it"),
+        ("before" = Option<String>, Query, description = "Cursor from pagination.prev, returns the page before it"),
+        ("total" = Option<String>, Query, description = "exact to count matching authors, estimate for a cheap guess of the collection size"),
+    )
 )]
 pub async fn get_all_authors(params: PageQueryParam, search_param: Option<String>, db: &SqlitePool) -> Result<impl Reply, Rejection> {
-    let page = params.page.unwrap_or(1);
-    let items_per_page = 10;
-    let offset = (page - 1) * items_per_page;
-
-    // Build the SQL query based on search criteria
-    let mut query = "
-        SELECT
-            id,
-            name,
-            surname,
-            photo,
-            created_at,
-            updated_at
-        FROM authors
-    ".to_owned();
-
+    let listing = Listing::parse(&AUTHORS, &params, search_param.as_deref())?;
+    let fts = search_param.as_deref().map(fts_query).transpose()?;
 
+    let mut count = authors_query("SELECT COUNT(*)", fts.as_deref());
+    listing.push_filters(&mut count, "a");
+    let total = listing.total(db, &AUTHORS, count).await?;
 
     //IF PARAM IS NOT EMPTY
-    if let Some(search) = search_param {
-
-
-        query.push_str(&format!(" WHERE name LIKE '{}' OR surname LIKE '{}'", search, search));
+    if fts.is_some() {
+        let mut query = authors_query(&format!("
+            SELECT
+                a.id, a.name, a.surname, a.created_at, a.updated_at, a.created_by, a.photo_sha256,
+                {} AS snippet,
+                bm25(authors_fts) AS score", AUTHOR_SNIPPET), fts.as_deref());
+        listing.push_filters(&mut query, "a");
+        listing.push_cursor(&mut query, "a");
+        listing.push_order(&mut query, "a", Some("score"));
+        listing.push_limit(&mut query);
+
+        let rows: Vec<AuthorSearchHit> = query
+            .build_query_as()
+            .fetch_all(db)
+            .await
+            .map_err(ApiError::from)?;
+
+        let (mut authors, pagination) = listing.page(rows, total);
+        for hit in authors.iter_mut() {
+            hit.author.set_photo_url();
+            hit.snippet = highlight_html(&hit.snippet);
+        }
+        let response = AuthorSearchResponse {
+            status: "Success".to_string(),
+            results: authors.len(),
+            authors,
+            pagination,
+        };
+        return Ok(listing.reply(&response, "authors", &response.pagination)?);
     }
 
-    query.push_str(" LIMIT (?) OFFSET (?)");
-
-
-    match sqlx::query_as(&query)
-    .bind(items_per_page)
-    .bind(offset)
+    let mut query = authors_query("
+        SELECT
+            a.id,
+            a.name,
+            a.surname,
+            a.created_at,
+            a.updated_at,
+            a.created_by,
+            a.photo_sha256", None);
+    listing.push_filters(&mut query, "a");
+    listing.push_cursor(&mut query, "a");
+    listing.push_order(&mut query, "a", None);
+    listing.push_limit(&mut query);
+
+    match query.build_query_as::<Author>()
     .fetch_all(db).await {
-        Ok(authors) => {
-            let length = authors.len();
+        Ok(rows) => {
+            let (mut authors, pagination) = listing.page(rows, total);
+            for author in authors.iter_mut() {
+                author.set_photo_url();
+            }
             let response = AuthorResponse {
                 status: "Success".to_string(),
+                results: authors.len(),
                 authors,
-                results: length,
+                pagination: Some(pagination.clone()),
             };
-            Ok(warp::reply::json(&response))
+            Ok(listing.reply(&response, "authors", &pagination)?)
         }
-        Err(e) => {
-            eprintln!("Error fetching authors: {:?}", e);
-            Err(warp::reject::custom(ErrorResponse::new(e.to_string())))
+        Err(e) => Err(ApiError::from(e).into()),
+    }
+}
+
+// FROM/WHERE shared by the list, search and count queries
+fn authors_query<'a>(select: &str, fts: Option<&str>) -> QueryBuilder<'a, Sqlite> {
+    let mut query = QueryBuilder::new(select);
+    match fts {
+        Some(fts) => {
+            query.push(" FROM authors_fts JOIN authors a ON a.id = authors_fts.rowid WHERE authors_fts MATCH ");
+            query.push_bind(fts.to_string());
+        }
+        None => {
+            query.push(" FROM authors a WHERE 1 = 1");
         }
     }
+    query
 }
 
 //GET SINGLE AUTHOR
@@ -110,7 +229,7 @@ pub async fn get_all_authors(params: PageQueryParam, search_param: Option<String
     path = "/api/authors/{id}",
     responses(
         (status = 200, description = "Author found succescully", body = SingeAuthorResponse),
-        (status = NOT_FOUND, description = "Author was not found")
+        (status = NOT_FOUND, description = "Author was not found", body = ErrorBody)
     ),
     params(
         ("id" = u64, Path, description = "Author database id to get post for"),
@@ -119,7 +238,7 @@ pub async fn get_all_authors(params: PageQueryParam, search_param: Option<String
 pub async fn get_author(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejection>{
 
     let query = "
-        SELECT id, name, surname, photo, created_at, updated_at
+        SELECT id, name, surname, created_at, updated_at, created_by, photo_sha256
         FROM authors 
         WHERE id = ?
     ";
@@ -130,20 +249,17 @@ pub async fn get_author(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejectio
         .await
     {
         Ok(author) => {
-            if let Some(author) = author {
+            if let Some(mut author) = author {
+                author.set_photo_url();
                 Ok(warp::reply::json(&SingeAuthorResponse {
                     status: "Success".to_string(),
                     data: author,
                 }))
             } else {
-                Err(warp::reject::not_found())
+                Err(ApiError::NotFound("Author not found".to_string()).into())
             }
         }
-        Err(e) => {
-            eprintln!("Error fetching author: {:?}", e);
-            let error_response = ErrorResponse::new("Error fetching author".to_string());
-            Err(warp::reject::custom(error_response))
-        }
+        Err(e) => Err(ApiError::from(e).into()),
     }
 }
 
@@ -157,7 +273,11 @@ pub async fn get_author(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejectio
     path = "/api/posts",
     responses(
         (status = 201, description = "Author created successfully", body = SingeAuthorResponse),
-        (status = 400, description = "Bad Request"),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 413, description = "Photo is too large", body = ErrorBody),
+        (status = 415, description = "Photo is not a PNG or JPEG image, or WebP or GIF where enabled", body = ErrorBody),
+        (status = 422, description = "Photo could not be decoded, is too large in pixels or was flagged by the malware scanner", body = ErrorBody),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorBody),
     ),
     // security(
     //     ("bearer_auth" = [])
@@ -166,60 +286,37 @@ pub async fn get_author(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejectio
 )]
 pub async fn post_author(
     db: &SqlitePool,
+    blobs: &Blobs,
+    photos: &PhotoConfig,
     data: CreateAuthorRequest,
+    username: String,
     clients:Clients
 ) -> Result<impl Reply, Rejection> {
+    let user = get_current_user(db, &username).await?;
+
     // Decode the base64-encoded photo data
-    let photo_data = match &data.photo {
-        Some(photo) => {
-            let decoded = decode(photo);
-            match decoded {
-                Ok(decoded) => Some(decoded),
-                Err(_) => {
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                        warp::http::StatusCode::BAD_REQUEST,
-                    ));
-                }
-            }
-        }
-        None => None,
-    };
-    
-    if let Some(photo_data) = &photo_data {
-        if check_image_format(photo_data).is_none() || check_image_size(photo_data).is_none() {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                warp::http::StatusCode::BAD_REQUEST,
-            ));
-        }
-    }
+    let photo_data = decode_photo(blobs, &data.photo, photos).await?;
     let query = "
-        INSERT INTO authors (name, surname, photo)
+        INSERT INTO authors (name, surname, created_by)
         VALUES (?, ?, ?)
     ";
 
     // Insert author data into the database
-    if let Err(err) = sqlx::query(query)
+    let author_id = match sqlx::query(query)
         .bind(&data.name)
         .bind(&data.surname)
-        .bind(photo_data.as_ref().map(|s| String::from_utf8_lossy(s).to_string())) // Convert Vec<u8> to String
+        .bind(user.id)
         .execute(db)
         .await
     {
-        return Err(warp::reject::custom(MyError(err)));
-    }
-    send_message_to_clients("Author has been created".to_string(), &clients).await;
-    let author = Author {
-        id: Default::default(),
-        name: data.name.clone(),
-        surname: data.surname.clone(),
-        photo: photo_data.clone(),
-        created_at: Default::default(),
-        updated_at: Default::default(),
+        Ok(result) => result.last_insert_rowid(),
+        Err(err) => return Err(ApiError::from(err).into()),
     };
-
-
+    if let Some(photo) = &photo_data {
+        store_photo(db, blobs, author_id, Some(photo)).await?;
+    }
+    let author = stored_author(db, author_id).await?;
+    send_event_to_clients(Event::new(EventData::AuthorCreated(author.clone()), &username), &clients).await;
 
     let response = warp::reply::json(&SingeAuthorResponse {
         status: "Success".to_string(),
@@ -239,7 +336,11 @@ pub async fn post_author(
     path = "/api/authors/{id}",
     responses(
         (status = 201, description = "Author updated successfully", body = SingeAuthorResponse),
-        (status = 400, description = "Bad Request"),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 403, description = "Only the owner or an admin can change this author", body = ErrorBody),
+        (status = NOT_FOUND, description = "Author was not found", body = ErrorBody),
+        (status = 422, description = "Photo was flagged by the malware scanner", body = ErrorBody),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorBody),
     ),
     security(
         ("bearer_auth" = [])
@@ -247,42 +348,22 @@ pub async fn post_author(
 )]
 pub async fn update_author(
     db: &SqlitePool,
+    blobs: &Blobs,
+    photos: &PhotoConfig,
     data: UpdateAuthorRequest,
     author_id: i64,
+    username: String,
     clients:Clients
 ) -> Result<impl Reply, Rejection> {
+    authorize_author_change(db, author_id, &username).await?;
 
-    let photo_data = match &data.photo {
-        Some(photo) => {
-            let decoded = decode(photo);
-            match decoded {
-                Ok(decoded) => Some(decoded),
-                Err(_) => {
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                        warp::http::StatusCode::BAD_REQUEST,
-                    ));
-                }
-            }
-        }
-        None => None,
-    };
-    
-    if let Some(photo_data) = &photo_data {
-        if check_image_format(photo_data).is_none() || check_image_size(photo_data).is_none() {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                warp::http::StatusCode::BAD_REQUEST,
-            ));
-        }
-    }
+    let photo_data = decode_photo(blobs, &data.photo, photos).await?;
 
 
     let query = "
     UPDATE authors
     SET name = ?,
     surname = ?,
-    photo = ?,
     updated_at = ?
     WHERE id = ?
     ";
@@ -290,24 +371,20 @@ pub async fn update_author(
     let updated_at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
     
 
-    _ = sqlx::query(query)
+    sqlx::query(query)
         .bind(&data.name)
         .bind(&data.surname)
-        .bind(&photo_data) // Use default if photo is None
         .bind(&updated_at)
         .bind(author_id)
         .execute(db)
-        .await;
-
-    let author = Author {
-        id: Default::default(),
-        name: data.name.clone(),
-        surname: data.surname.clone(),
-        photo: photo_data.clone(),
-        created_at: Default::default(),
-        updated_at: updated_at,
-    };
-    send_message_to_clients("Author has been updated".to_string(), &clients).await;
+        .await
+        .map_err(ApiError::from)?;
+
+    // A request without a photo clears it
+    store_photo(db, blobs, author_id, photo_data.as_deref()).await?;
+
+    let author = stored_author(db, author_id).await?;
+    send_event_to_clients(Event::new(EventData::AuthorUpdated(author.clone()), &username), &clients).await;
 
     let response = warp::reply::json(&SingeAuthorResponse {
         status: "Success".to_string(),
@@ -321,13 +398,76 @@ pub async fn update_author(
 }
 
 
+//SET AN AUTHOR'S PHOTO FROM A multipart/form-data BODY
+#[utoipa::path(
+    put,
+    path = "/api/authors/{id}/photo",
+    request_body(content = AuthorPhotoForm, content_type = "multipart/form-data", description = "A single \"photo\" part, PNG or JPEG, at most 500 kB. Stored re-encoded, without metadata"),
+    responses(
+        (status = 200, description = "Photo stored", body = SingeAuthorResponse),
+        (status = 400, description = "Malformed form or no photo part", body = ErrorBody),
+        (status = 403, description = "Only the owner or an admin can change this author", body = ErrorBody),
+        (status = NOT_FOUND, description = "Author was not found", body = ErrorBody),
+        (status = 411, description = "Content-Length header is missing", body = ErrorBody),
+        (status = 413, description = "Photo is too large", body = ErrorBody),
+        (status = 415, description = "Photo is not a PNG or JPEG image, or WebP or GIF where enabled", body = ErrorBody),
+        (status = 422, description = "Photo could not be decoded, is too large in pixels or was flagged by the malware scanner", body = ErrorBody),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorBody),
+    ),
+    params(
+        ("id" = u64, Path, description = "Author id"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn upload_author_photo(db: &SqlitePool, blobs: &Blobs, photos: &PhotoConfig, author_id: i64, username: String, mut form: FormData, clients: Clients) -> Result<impl Reply, Rejection> {
+    // Check permissions before reading any of the body
+    authorize_author_change(db, author_id, &username).await?;
+
+    let mut photo = None;
+    while let Some(part) = next_part(&mut form).await? {
+        if part.name() != "photo" || photo.is_some() {
+            return Err(ApiError::BadRequest("The form must contain exactly one \"photo\" part".to_string()).into());
+        }
+        photo = Some(read_part(part, MAX_IMAGE_SIZE, "Photo must not be larger than 500 kB").await?);
+    }
+
+    let photo = photo.ok_or_else(|| ApiError::BadRequest("The form must contain exactly one \"photo\" part".to_string()))?;
+    scan_photo(blobs, &photo).await?;
+    let photo = normalize_photo(photo, photos).await?;
+
+    store_photo(db, blobs, author_id, Some(&photo)).await?;
+
+    let updated_at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+
+    sqlx::query("UPDATE authors SET updated_at = ? WHERE id = ?")
+        .bind(&updated_at)
+        .bind(author_id)
+        .execute(db)
+        .await
+        .map_err(ApiError::from)?;
+
+    let author = stored_author(db, author_id).await?;
+
+    send_event_to_clients(Event::new(EventData::AuthorUpdated(author.clone()), &username), &clients).await;
+
+    Ok(warp::reply::json(&SingeAuthorResponse {
+        status: "Success".to_string(),
+        data: author,
+    }))
+}
+
+
 //DELTE AUTHOR
 #[utoipa::path(
     delete,
     path = "/api/authors/{id}",
     responses(
         (status = 200, description = "Author deleted succescully"),
-        (status = NOT_FOUND, description = "Author was not found")
+        (status = 403, description = "Only the owner or an admin can delete this author", body = ErrorBody),
+        (status = NOT_FOUND, description = "Author was not found", body = ErrorBody),
+        (status = 409, description = "Author still has posts", body = ErrorBody)
     ),
     params(
         ("id" = u64, Path, description = "Post id"),
@@ -336,23 +476,39 @@ pub async fn update_author(
         ("bearer_auth" = [])
     )
 )]
-pub async fn delete_author(db: &SqlitePool, id: i64, clients:Clients)-> Result<impl Reply, Rejection>{
+pub async fn delete_author(db: &SqlitePool, blobs: &Blobs, id: i64, username: String, clients:Clients)-> Result<impl Reply, Rejection>{
+    authorize_author_change(db, id, &username).await?;
+
+    let photo: (String, Option<String>) = sqlx::query_as("SELECT photo_storage, photo_sha256 FROM authors WHERE id = ?")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .map_err(ApiError::from)?;
+    // Sent along with the event
+    let author = stored_author(db, id).await?;
 
     let query = "
         DELETE FROM authors
         where id = ?
     ";
 
-    _ = sqlx::query(query)
+    sqlx::query(query)
     .bind(id)
     .execute(db)
-    .await;
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_error) if db_error.is_foreign_key_violation() => {
+            ApiError::Conflict("Author still has posts".to_string())
+        }
+        e => ApiError::from(e),
+    })?;
+    release_photo(db, blobs, &photo.0, photo.1.as_deref()).await?;
 
     let response = warp::reply::json(&StatusResponse {
         status: "Success".to_string(),
 
     });
-    send_message_to_clients("Author has been deleted".to_string(), &clients).await;
+    send_event_to_clients(Event::new(EventData::AuthorDeleted(author), &username), &clients).await;
 
     Ok(warp::reply::with_status(
         response,
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use uuid::Uuid;
+use warp::http::header::{HeaderMap, AUTHORIZATION};
+use warp::{sse, Rejection, Reply};
+
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::models::response::EventsQueryParam;
+use crate::ws::clients::{Client, ClientGuard, ClientSender, Clients};
+use crate::ws::topics::{parse_topic_list, Topic};
+use crate::ws::ws_handler::token_ended;
+use crate::Middleware::auth::{jwt_from_header, validate_jwt};
+
+//STREAM CHANGE EVENTS AS SERVER-SENT EVENTS
+//
+// The same events /ws sends, each with its seq as id. A client reconnecting
+// with Last-Event-ID or ?since= first gets the events it missed. The stream
+// ends when the token expires or is revoked
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    responses(
+        (status = 200, description = "An event stream, each event's data is the JSON a WebSocket client would receive", content_type = "text/event-stream"),
+        (status = 400, description = "Unknown topic or invalid Last-Event-ID", body = ErrorBody),
+        (status = 401, description = "Missing, invalid or expired token", body = ErrorBody),
+    ),
+    params(
+        ("token" = Option<String>, Query, description = "Access token, for clients that can't send an Authorization header"),
+        ("topics" = Option<String>, Query, description = "Comma separated topics like posts,author:7:posts, posts and authors when not given"),
+        ("since" = Option<i64>, Query, description = "Seq of the last event received, to first get the ones after it"),
+        ("Last-Event-ID" = Option<i64>, Header, description = "Sent by EventSource when it reconnects, takes the place of since"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_events(db: SqlitePool, config: Arc<Config>, clients: Clients, headers: HeaderMap, query: EventsQueryParam) -> Result<impl Reply, Rejection> {
+    let token = match query.token {
+        Some(token) if !headers.contains_key(AUTHORIZATION) => token,
+        _ => jwt_from_header(&headers)?,
+    };
+    let claims = validate_jwt(&db, &config, &token).await?;
+
+    let topics = match query.topics {
+        Some(topics) => parse_topic_list(&topics).map_err(ApiError::BadRequest)?,
+        None => HashSet::from([Topic::Posts, Topic::Authors]),
+    };
+
+    let since = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value.to_str().ok().and_then(|value| value.trim().parse::<i64>().ok())
+                .ok_or_else(|| ApiError::BadRequest("Last-Event-ID must be an event seq".to_string()))?,
+        ),
+        None => query.since,
+    };
+
+    // Missed events are queued before the client can receive new ones
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let client_id = Uuid::new_v4().to_simple().to_string();
+    let client = Client {
+        client_id: client_id.clone(),
+        sender: Some(ClientSender::EventStream(sender)),
+        username: claims.sub.clone(),
+        role: claims.role,
+        topics,
+    };
+    clients.join(client, since).await?;
+
+    // The stream owns the guard, so the client leaves Clients once the token
+    // expires or is revoked, or the connection goes away
+    let guard = ClientGuard::new(clients, client_id);
+    let events = UnboundedReceiverStream::new(receiver)
+        .map(move |(seq, message)| {
+            let _ = &guard;
+            Ok::<_, Infallible>(sse::Event::default().id(seq.to_string()).data(message))
+        })
+        .take_until(token_ended(db, claims));
+
+    Ok(sse::reply(sse::keep_alive().stream(events)))
+}
@@ -0,0 +1,384 @@
+use sqlx::SqlitePool;
+use warp::http::header::{
+    HeaderMap, HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    IF_NONE_MATCH, IF_RANGE, RANGE, X_CONTENT_TYPE_OPTIONS,
+};
+use warp::http::{Method, StatusCode};
+use warp::hyper::Body;
+use warp::{Rejection, Reply};
+
+use crate::db::files::{get_file_metadata, list_files, FileReader};
+use crate::errors::ApiError;
+use crate::models::files::FileMetadata;
+use crate::models::response::{DownloadQueryParam, PhotoQueryParam};
+use crate::storage::archive::zip_stream;
+use crate::storage::thumbnails::{self, ThumbnailSize};
+use crate::storage::{BlobOwner, Blobs};
+use crate::Middleware::sniff;
+
+// Size of the pieces the response body is read and sent in
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+//DOWNLOAD A SINGLE FILE AS RAW BYTES
+#[utoipa::path(
+    get,
+    path = "/api/files/{id}",
+    responses(
+        (status = 200, description = "The file's bytes, with its MIME type as Content-Type", content_type = "application/octet-stream"),
+        (status = 206, description = "The part of the file asked for with a Range header", content_type = "application/octet-stream"),
+        (status = 304, description = "The file matches the If-None-Match ETag"),
+        (status = 403, description = "The file was quarantined by the malware scanner", body = ErrorBody),
+        (status = NOT_FOUND, description = "File was not found", body = ErrorBody),
+        (status = 416, description = "Range is outside the file", body = ErrorBody),
+    ),
+    params(
+        ("id" = u64, Path, description = "File id"),
+        ("download" = Option<bool>, Query, description = "Send Content-Disposition: attachment instead of inline"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_file(db: &SqlitePool, blobs: &Blobs, file_id: i64, method: Method, headers: HeaderMap, query: DownloadQueryParam) -> Result<impl Reply, Rejection> {
+    let file = get_file_metadata(db, file_id).await?;
+    if file.is_quarantined() {
+        return Err(ApiError::Forbidden("File was quarantined by the malware scanner and can't be downloaded".to_string()).into());
+    }
+    let size = file.size.max(0) as u64;
+    let etag = file.sha256.as_ref().map(|sha256| format!("\"{}\"", sha256));
+
+    let mut response = warp::reply::Response::new(Body::empty());
+    // Browsers must not guess a type for uploads served inline
+    response.headers_mut().insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+    if let Some(etag) = &etag {
+        response.headers_mut().insert(ETAG, header_value(etag)?);
+        if matches_etag(headers.get(IF_NONE_MATCH), etag) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(response);
+        }
+    }
+
+    // A Range is only honoured while the client's copy (If-Range) is still current
+    let range = match headers.get(RANGE).and_then(|range| range.to_str().ok()) {
+        Some(range) if if_range_matches(headers.get(IF_RANGE), etag.as_deref()) => parse_range(range, size)?,
+        _ => None,
+    };
+    let (start, length) = range.unwrap_or((0, size));
+
+    let disposition = if query.download.unwrap_or(false) { "attachment" } else { "inline" };
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, header_value(&file.mime_type)?);
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    headers.insert(CONTENT_DISPOSITION, header_value(&content_disposition(disposition, &file))?);
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+
+    if range.is_some() {
+        let content_range = format!("bytes {}-{}/{}", start, start + length - 1, size);
+        headers.insert(CONTENT_RANGE, header_value(&content_range)?);
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    }
+
+    if method != Method::HEAD && length > 0 {
+        *response.body_mut() = range_body(blobs.clone(), file, start, length);
+    }
+
+    Ok(response)
+}
+
+// The bytes of a file from start on, read a chunk at a time as the client takes them
+fn range_body(blobs: Blobs, file: FileMetadata, start: u64, length: u64) -> Body {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let end = start + length;
+        let mut offset = start;
+        let mut reader = match FileReader::new(&blobs, &file, start, length) {
+            Ok(reader) => reader,
+            Err(e) => {
+                eprintln!("Download of file {} failed: {:?}", file.id, e);
+                sender.abort();
+                return;
+            }
+        };
+        while offset < end {
+            let chunk = match reader.next_chunk(CHUNK_SIZE).await {
+                Ok(chunk) if !chunk.is_empty() => chunk,
+                Ok(_) => {
+                    eprintln!("File {} ended at byte {}, expected {}", file.id, offset, end);
+                    sender.abort();
+                    return;
+                }
+                Err(e) => {
+                    // Headers are sent already, a short body is all the client can be told
+                    eprintln!("Download of file {} failed: {:?}", file.id, e);
+                    sender.abort();
+                    return;
+                }
+            };
+            offset += chunk.len() as u64;
+            // The client went away
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+    });
+    body
+}
+
+//DOWNLOAD EVERY FILE OF A POST AS ONE ZIP ARCHIVE
+#[utoipa::path(
+    get,
+    path = "/api/posts/{id}/files.zip",
+    responses(
+        (status = 200, description = "A ZIP archive of the post's files under their stored names, without quarantined ones. Names used twice get \" (2)\", \" (3)\", ... added", content_type = "application/zip"),
+        (status = NOT_FOUND, description = "Post was not found", body = ErrorBody),
+        (status = 413, description = "The files are too large for one archive", body = ErrorBody),
+    ),
+    params(
+        ("id" = u64, Path, description = "Post id"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_post_files_zip(db: &SqlitePool, blobs: &Blobs, post_id: i64) -> Result<impl Reply, Rejection> {
+    let post: Option<(i64,)> = sqlx::query_as("SELECT id FROM posts WHERE id = ?")
+        .bind(post_id)
+        .fetch_optional(db)
+        .await
+        .map_err(ApiError::from)?;
+    if post.is_none() {
+        return Err(ApiError::NotFound("Post not found".to_string()).into());
+    }
+
+    let mut files = list_files(db, post_id).await?;
+    files.retain(|file| !file.is_quarantined());
+    let body = zip_stream(blobs.clone(), files)?;
+
+    let mut response = warp::reply::Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(CONTENT_DISPOSITION, header_value(&format!("attachment; filename=\"post-{}-files.zip\"", post_id))?);
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+
+    Ok(response)
+}
+
+//DOWNLOAD AN AUTHOR'S PHOTO, OR A THUMBNAIL OF IT WITH ?w= AND ?h=
+#[utoipa::path(
+    get,
+    path = "/api/authors/{id}/photo",
+    responses(
+        (status = 200, description = "The photo, or a scaled down copy that fits in w x h", content_type = "image/png"),
+        (status = 304, description = "The photo matches the If-None-Match ETag"),
+        (status = 400, description = "w or h is out of range", body = ErrorBody),
+        (status = NOT_FOUND, description = "Author was not found or has no photo", body = ErrorBody),
+    ),
+    params(
+        ("id" = u64, Path, description = "Author id"),
+        ("w" = Option<u32>, Query, description = "Maximum width, 1 to 1024"),
+        ("h" = Option<u32>, Query, description = "Maximum height, 1 to 1024"),
+    )
+)]
+pub async fn get_author_photo(db: &SqlitePool, blobs: &Blobs, author_id: i64, method: Method, headers: HeaderMap, query: PhotoQueryParam) -> Result<impl Reply, Rejection> {
+    let size = ThumbnailSize::parse(query.w, query.h)?;
+
+    let photo: Option<(String, Option<String>)> = sqlx::query_as("SELECT photo_storage, photo_sha256 FROM authors WHERE id = ?")
+        .bind(author_id)
+        .fetch_optional(db)
+        .await
+        .map_err(ApiError::from)?;
+    let (storage, sha256) = match photo {
+        None => return Err(ApiError::NotFound("Author not found".to_string()).into()),
+        Some((_, None)) => return Err(ApiError::NotFound("Author has no photo".to_string()).into()),
+        Some((storage, Some(sha256))) => (storage, sha256),
+    };
+
+    let etag = match size {
+        Some(size) => format!("\"{}-{}\"", sha256, size.tag()),
+        None => format!("\"{}\"", sha256),
+    };
+
+    let mut response = warp::reply::Response::new(Body::empty());
+    response.headers_mut().insert(ETAG, header_value(&etag)?);
+    response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("public, no-cache"));
+    response.headers_mut().insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    if matches_etag(headers.get(IF_NONE_MATCH), &etag) {
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        return Ok(response);
+    }
+
+    let data = match size {
+        Some(size) => thumbnails::thumbnail(db, blobs, author_id, &storage, &sha256, size).await?,
+        None => blobs.read_all(&storage, BlobOwner::AuthorPhoto(author_id), &sha256).await?,
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(sniff::detect(&data)));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(data.len()));
+
+    if method != Method::HEAD {
+        *response.body_mut() = Body::from(data);
+    }
+
+    Ok(response)
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, ApiError> {
+    HeaderValue::from_str(value).map_err(|_| ApiError::Internal(format!("Invalid header value {:?}", value)))
+}
+
+fn matches_etag(header: Option<&HeaderValue>, etag: &str) -> bool {
+    match header.and_then(|value| value.to_str().ok()) {
+        Some(value) => value.split(',').map(str::trim).any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag),
+        None => false,
+    }
+}
+
+// If-Range may also hold a date, which is never considered current since files have no Last-Modified
+fn if_range_matches(header: Option<&HeaderValue>, etag: Option<&str>) -> bool {
+    match (header.and_then(|value| value.to_str().ok()), etag) {
+        (None, _) => true,
+        (Some(value), Some(etag)) => value.trim() == etag,
+        (Some(_), None) => false,
+    }
+}
+
+//PARSE A SINGLE "bytes=" RANGE INTO (start, length)
+//
+// Multiple ranges and unknown units are ignored, which RFC 9110 allows, and
+// the whole file is sent instead. Ranges that start past the end are a 416
+fn parse_range(header: &str, size: u64) -> Result<Option<(u64, u64)>, ApiError> {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    let range = match (first.trim(), last.trim()) {
+        // bytes=-500 is the last 500 bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) | Err(_) => return Ok(None),
+            Ok(_) if size == 0 => return Err(ApiError::RangeNotSatisfiable(size)),
+            Ok(suffix) => (size.saturating_sub(suffix), size.min(suffix)),
+        },
+        (first, last) => {
+            let start = match first.parse::<u64>() {
+                Ok(start) => start,
+                Err(_) => return Ok(None),
+            };
+            let end = match last {
+                "" => size.saturating_sub(1),
+                last => match last.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(size.saturating_sub(1)),
+                    _ => return Ok(None),
+                },
+            };
+            if start >= size {
+                return Err(ApiError::RangeNotSatisfiable(size));
+            }
+            (start, end - start + 1)
+        }
+    };
+
+    Ok(Some(range))
+}
+
+// inline; filename="report.pdf"; filename*=UTF-8''report.pdf
+fn content_disposition(disposition: &str, file: &FileMetadata) -> String {
+    let filename = file.filename.clone().unwrap_or_else(|| format!("file-{}", file.id));
+    let ascii: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect();
+
+    format!("{}; filename=\"{}\"; filename*=UTF-8''{}", disposition, ascii, encode_rfc5987(&filename))
+}
+
+fn encode_rfc5987(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(header: &str, size: u64) -> Option<(u64, u64)> {
+        parse_range(header, size).unwrap()
+    }
+
+    fn unsatisfiable(header: &str, size: u64) -> bool {
+        matches!(parse_range(header, size), Err(ApiError::RangeNotSatisfiable(s)) if s == size)
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(range("bytes=500-", 1000), Some((500, 500)));
+        assert_eq!(range(" bytes= 10 - 19 ", 1000), Some((10, 10)));
+        // An end past the file is cut to its last byte
+        assert_eq!(range("bytes=900-5000", 1000), Some((900, 100)));
+        assert_eq!(range("bytes=999-999", 1000), Some((999, 1)));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(range("bytes=-100", 1000), Some((900, 100)));
+        // Longer than the file is the whole file
+        assert_eq!(range("bytes=-5000", 1000), Some((0, 1000)));
+        assert_eq!(range("bytes=-0", 1000), None);
+        assert!(unsatisfiable("bytes=-10", 0));
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert!(unsatisfiable("bytes=1000-", 1000));
+        assert!(unsatisfiable("bytes=2000-3000", 1000));
+        assert!(unsatisfiable("bytes=0-", 0));
+    }
+
+    #[test]
+    fn ranges_sent_whole_instead() {
+        // Multiple ranges, other units, reversed or malformed bounds
+        assert_eq!(range("bytes=0-9,20-29", 1000), None);
+        assert_eq!(range("items=0-9", 1000), None);
+        assert_eq!(range("bytes=50-10", 1000), None);
+        assert_eq!(range("bytes=abc-10", 1000), None);
+        assert_eq!(range("bytes=10", 1000), None);
+    }
+
+    #[test]
+    fn if_range_needs_the_current_etag() {
+        let current = HeaderValue::from_static("\"abc\"");
+        let stale = HeaderValue::from_static("\"old\"");
+        let date = HeaderValue::from_static("Sat, 18 Oct 2026 13:36:59 GMT");
+
+        assert!(if_range_matches(None, Some("\"abc\"")));
+        assert!(if_range_matches(Some(&current), Some("\"abc\"")));
+        assert!(!if_range_matches(Some(&stale), Some("\"abc\"")));
+        assert!(!if_range_matches(Some(&date), Some("\"abc\"")));
+        assert!(!if_range_matches(Some(&current), None));
+    }
+
+    #[test]
+    fn if_none_match_lists_and_weak_tags() {
+        let etag = "\"abc\"";
+        assert!(matches_etag(Some(&HeaderValue::from_static("\"x\", W/\"abc\"")), etag));
+        assert!(matches_etag(Some(&HeaderValue::from_static("*")), etag));
+        assert!(!matches_etag(Some(&HeaderValue::from_static("\"x\"")), etag));
+        assert!(!matches_etag(None, etag));
+    }
+}
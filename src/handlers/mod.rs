@@ -1,3 +1,7 @@
 pub mod author_handler;
+pub mod events_handler;
+pub mod files_handler;
+pub mod multipart;
 pub mod posts_handler;
-pub mod user_handlers;
\ No newline at end of file
+pub mod uploads_handler;
+pub mod user_handlers;
@@ -0,0 +1,30 @@
+use bytes::{Buf, BufMut};
+use futures::StreamExt;
+use warp::multipart::{FormData, Part};
+
+use crate::errors::ApiError;
+
+//NEXT PART OF A MULTIPART FORM, None ONCE THE FORM IS DONE
+pub async fn next_part(form: &mut FormData) -> Result<Option<Part>, ApiError> {
+    match form.next().await {
+        Some(Ok(part)) => Ok(Some(part)),
+        Some(Err(e)) => Err(ApiError::BadRequest(format!("Malformed multipart body: {}", e))),
+        None => Ok(None),
+    }
+}
+
+//READ ONE PART CHUNK BY CHUNK, GIVING UP AS SOON AS IT GROWS PAST max_size
+pub async fn read_part(part: Part, max_size: usize, too_large: &str) -> Result<Vec<u8>, ApiError> {
+    let mut data = Vec::new();
+    let mut chunks = part.stream();
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(format!("Malformed multipart body: {}", e)))?;
+        if data.len() + chunk.remaining() > max_size {
+            return Err(ApiError::PayloadTooLarge(too_large.to_string()));
+        }
+        data.put(chunk);
+    }
+
+    Ok(data)
+}
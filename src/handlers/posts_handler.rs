@@ -1,76 +1,215 @@
+use std::collections::HashSet;
+
 use crate::ws::clients::Clients;
-use crate::ws::ws_handler::send_message_to_clients;
-use crate::Middleware::mime_check::check_file_size;
+use crate::ws::events::{Event, EventData};
+use crate::ws::ws_handler::send_event_to_clients;
+use crate::Middleware::mime_check::{check_file_size, MAX_FILE_SIZE};
+use crate::handlers::multipart::{next_part, read_part};
 use crate::models::posts::Post;
-use crate::models::response::{SingePostResponse, PostResponse, CreatePostRequest, UpdatePostRequest, StatusResponse, PageQueryParam, FileResponse};
-
-use sqlx::{SqlitePool, Row};
+use crate::models::response::{SingePostResponse, PostResponse, CreatePostRequest, UpdatePostRequest, StatusResponse, PageQueryParam, FileResponse, PostSearchHit, PostSearchResponse, UploadResponse, FileListResponse};
+use crate::db::listing::{Listing, POSTS};
+use crate::db::search::{fts_query, highlight_html, POST_SNIPPET};
+
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use crate::Middleware::auth::get_current_user;
+use crate::db::files::{delete_file_rows, delete_post_files, insert_file_rows, list_files, release_files, store_files, NewFile};
+use crate::db::ImmediateTx;
+use crate::storage::{BlobOwner, Blobs};
+use crate::config::Config;
+use crate::db::quotas::ensure_quota;
+use crate::Middleware::auth::CurrentUser;
+use crate::db::uploads::{claim_uploads, completed_uploads};
+use crate::errors::ApiError;
 use base64::decode;
+use warp::multipart::FormData;
 use warp::{ Rejection, Reply};
-use serde:: Serialize;
 
 
 
-#[derive(Debug, Serialize)]
-pub struct ErrorResponse {
-    pub message: String,
+//DECODE AND VALIDATE BASE64 FILES FROM A REQUEST BODY
+fn decode_files(files: &Option<Vec<String>>, allowed: &[String]) -> Result<Option<Vec<NewFile>>, ApiError> {
+    let files = match files {
+        Some(files) => files,
+        None => return Ok(None),
+    };
+
+    let mut file_data = Vec::new();
+    for file in files {
+        let decoded = decode(file).map_err(|_| ApiError::BadRequest("Uploaded file is not valid base64".to_string()))?;
+        if check_file_size(&decoded).is_none() {
+            return Err(ApiError::PayloadTooLarge("Uploaded files must not be larger than 10 MB".to_string()));
+        }
+        let file = NewFile::new(None, decoded);
+        file.check_allowed(allowed)?;
+        file_data.push(file);
+    }
+
+    Ok(Some(file_data))
 }
 
-impl ErrorResponse {
-    pub fn new(message: String) -> Self {
-        ErrorResponse { message }
+//THE FILES OF A CREATE OR UPDATE REQUEST: BASE64 ONES AND FINISHED RESUMABLE UPLOADS
+//
+// Checked against the allow-list. Also returns the bytes the resumable
+// uploads hold in the user's quota, which attaching them frees again
+async fn request_files(
+    db: &SqlitePool,
+    user: &CurrentUser,
+    uploaded_files: &Option<Vec<String>>,
+    upload_ids: &Option<Vec<String>>,
+    allowed: &[String],
+) -> Result<(Option<Vec<NewFile>>, i64), ApiError> {
+    let mut files = decode_files(uploaded_files, allowed)?;
+
+    let mut reserved = 0;
+    if let Some(upload_ids) = upload_ids {
+        // Attached twice, an upload would also be given back twice
+        let mut seen = HashSet::new();
+        if let Some(id) = upload_ids.iter().find(|id| !seen.insert(id.as_str())) {
+            return Err(ApiError::Validation(format!("Upload {} is listed more than once", id)));
+        }
+
+        let uploaded = completed_uploads(db, user.id, upload_ids).await?;
+        for file in &uploaded {
+            file.check_allowed(allowed)?;
+            reserved += file.data.len() as i64;
+        }
+        files.get_or_insert_with(Vec::new).extend(uploaded);
     }
+
+    Ok((files, reserved))
 }
 
-impl warp::reject::Reject for ErrorResponse {}
+fn total_size(files: &[NewFile]) -> i64 {
+    files.iter().map(|file| file.data.len() as i64).sum()
+}
+
+//RUN EVERY FILE THROUGH THE MALWARE SCANNER BEFORE IT IS STORED
+//
+// Flagged files are still stored, quarantined, so an admin can look at them.
+// Downloads never return them
+async fn scan_files(blobs: &Blobs, files: &mut Option<Vec<NewFile>>) -> Result<(), ApiError> {
+    for file in files.iter_mut().flatten() {
+        file.scan(blobs.scanner()).await?;
+    }
+    Ok(())
+}
 
+//CHECK THAT THE CALLER OWNS THE POST (OR IS AN ADMIN), RETURNS THE POST'S AUTHOR AND OWNER
+async fn authorize_post_change(db: &SqlitePool, post_id: i64, username: &str) -> Result<(i64, Option<i64>), Rejection> {
+    let user = get_current_user(db, username).await?;
 
+    let post: Option<(i64, Option<i64>)> = sqlx::query_as("SELECT author_id, created_by FROM posts WHERE id = ?")
+        .bind(post_id)
+        .fetch_optional(db)
+        .await
+        .map_err(ApiError::from)?;
 
-//GET ALL POSTS
+    match post {
+        None => Err(ApiError::NotFound("Post not found".to_string()).into()),
+        Some((author_id, created_by)) if user.can_modify(created_by) => Ok((author_id, created_by)),
+        Some(_) => Err(ApiError::Forbidden("Only the owner or an admin can change this post".to_string()).into()),
+    }
+}
+
+
+
+//GET ALL POSTS, OR SEARCH THEM WITH ?q=
 #[utoipa::path(
     get,
     path = "/api/posts",
     responses(
-        (status = 200, description = "Authors found succescully", body = PostResponse),
-        (status = NOT_FOUND, description = "Authors was not found")
+        (status = 200, description = "Posts found succescully, ranked search hits when q is given", body = PostResponse),
+        (status = 400, description = "Invalid search, filter, sort or fields parameter", body = ErrorBody),
+        (status = 500, description = "Posts could not be loaded", body = ErrorBody)
+    ),
+    params(
+        ("page" = Option<u32>, Query, description = "Page number, starting at 1. Prefer the after/before cursors"),
+        ("limit" = Option<i64>, Query, description = "Posts per page, default 10, at most 100"),
+        ("q" = Option<String>, Query, description = "Full text search over title and content. Supports \"phrases\" and prefix* terms"),
+        ("sort" = Option<String>, Query, description = "Comma separated sort keys, - for descending: -created_at,title"),
+        ("fields" = Option<String>, Query, description = "Comma separated fields to return: id,title"),
+        ("author_id" = Option<i64>, Query, description = "Only posts of this author"),
+        ("created_after" = Option<String>, Query, description = "Only posts created after this date or timestamp"),
+        ("created_before" = Option<String>, Query, description = "Only posts created before this date or timestamp"),
+        ("after" = Option<String>, Query, description = "Cursor from pagination.next, returns the page after it"),
+        ("before" = Option<String>, Query, description = "Cursor from pagination.prev, returns the page before it"),
+        ("total" = Option<String>, Query, description = "exact to count matching posts, estimate for a cheap guess of the collection size"),
     )
 )]
 pub async fn get_all_posts(params: PageQueryParam, search_param: Option<String>, db: &SqlitePool) -> Result<impl Reply, Rejection> {
-    let page = params.page.unwrap_or(1);
-    let items_per_page = 10;
-    let offset = (page-1) * items_per_page;
-
-    let mut query = "
-        SELECT
-            *
-        FROM posts
-        
-        ".to_owned();
-
-    if let Some(search) = search_param{
-        query.push_str(&format!(" WHERE name LIKE '{}' OR surname LIKE '{}'", search, search));
+    let listing = Listing::parse(&POSTS, &params, search_param.as_deref())?;
+    let fts = search_param.as_deref().map(fts_query).transpose()?;
+
+    let mut count = posts_query("SELECT COUNT(*)", fts.as_deref());
+    listing.push_filters(&mut count, "p");
+    let total = listing.total(db, &POSTS, count).await?;
+
+    if fts.is_some() {
+        // Title matches weigh more than content matches
+        let mut query = posts_query(&format!("
+            SELECT
+                p.id, p.title, p.content, p.author_id, p.created_at, p.updated_at, p.created_by,
+                {} AS snippet,
+                bm25(posts_fts, 10.0, 1.0) AS score", POST_SNIPPET), fts.as_deref());
+        listing.push_filters(&mut query, "p");
+        listing.push_cursor(&mut query, "p");
+        listing.push_order(&mut query, "p", Some("score"));
+        listing.push_limit(&mut query);
+
+        let rows: Vec<PostSearchHit> = query
+            .build_query_as()
+            .fetch_all(db)
+            .await
+            .map_err(ApiError::from)?;
+
+        let (mut posts, pagination) = listing.page(rows, total);
+        for hit in posts.iter_mut() {
+            hit.snippet = highlight_html(&hit.snippet);
+        }
+        let response = PostSearchResponse {
+            status: "Success".to_string(),
+            results: posts.len(),
+            posts,
+            pagination,
+        };
+        return Ok(listing.reply(&response, "posts", &response.pagination)?);
     }
 
-    query.push_str(" LIMIT (?) OFFSET (?)");
+    let mut query = posts_query("SELECT p.id, p.title, p.content, p.author_id, p.created_at, p.updated_at, p.created_by", None);
+    listing.push_filters(&mut query, "p");
+    listing.push_cursor(&mut query, "p");
+    listing.push_order(&mut query, "p", None);
+    listing.push_limit(&mut query);
 
-    match sqlx::query_as(&query)
-    .bind(items_per_page)
-    .bind(offset)
+    match query.build_query_as::<Post>()
     .fetch_all(db).await {
-        Ok(posts) => {
-            let length = posts.len();
+        Ok(rows) => {
+            let (posts, pagination) = listing.page(rows, total);
             let response = PostResponse {
                 status: "Success".to_string(),
+                results: posts.len(),
                 posts,
-                results: length,
+                pagination: Some(pagination.clone()),
             };
-            Ok(warp::reply::json(&response))
+            Ok(listing.reply(&response, "posts", &pagination)?)
         }
-        Err(e) => {
-            eprintln!("Error fetching posts: {:?}", e);
-            Err(warp::reject::custom(ErrorResponse::new(e.to_string())))
+        Err(e) => Err(ApiError::from(e).into()),
+    }
+}
+
+// FROM/WHERE shared by the list, search and count queries
+fn posts_query<'a>(select: &str, fts: Option<&str>) -> QueryBuilder<'a, Sqlite> {
+    let mut query = QueryBuilder::new(select);
+    match fts {
+        Some(fts) => {
+            query.push(" FROM posts_fts JOIN posts p ON p.id = posts_fts.rowid WHERE posts_fts MATCH ");
+            query.push_bind(fts.to_string());
+        }
+        None => {
+            query.push(" FROM posts p WHERE 1 = 1");
         }
     }
+    query
 }
 
 
@@ -82,7 +221,7 @@ pub async fn get_all_posts(params: PageQueryParam, search_param: Option<String>,
     path = "/api/posts/{id}",
     responses(
         (status = 200, description = "Post found succescully", body = SingePostResponse),
-        (status = NOT_FOUND, description = "Post was not found")
+        (status = NOT_FOUND, description = "Post was not found", body = ErrorBody)
     ),
     params(
         ("id" = u64, Path, description = "Post database id to get post for"),
@@ -91,7 +230,7 @@ pub async fn get_all_posts(params: PageQueryParam, search_param: Option<String>,
 pub async fn get_post(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejection>{
 
     let query = "
-        SELECT id, title, content, author_id, uploaded_file, created_at, updated_at
+        SELECT id, title, content, author_id, created_at, updated_at, created_by
         FROM posts 
         WHERE id = ?
     ";
@@ -108,14 +247,10 @@ pub async fn get_post(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejection>
                     data: post,
                 }))
             } else {
-                Err(warp::reject::not_found())
+                Err(ApiError::NotFound("Post not found".to_string()).into())
             }
         }
-        Err(e) => {
-            eprintln!("Error fetching post: {:?}", e);
-            let error_response = ErrorResponse::new("Error fetching post".to_string());
-            Err(warp::reject::custom(error_response))
-        }
+        Err(e) => Err(ApiError::from(e).into()),
     }
 }
 
@@ -133,7 +268,12 @@ pub async fn get_post(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejection>
     path = "/api/posts",
     responses(
         (status = 201, description = "Post created successfully", body = SingePostResponse),
-        (status = 400, description = "Bad Request"),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 413, description = "An uploaded file is too large", body = ErrorBody),
+        (status = 415, description = "An uploaded file's type is not accepted", body = ErrorBody),
+        (status = 422, description = "Author does not exist, or an upload id is unknown or unfinished", body = ErrorBody),
+        (status = 507, description = "The files don't fit in the user's storage quota", body = ErrorBody),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorBody),
     ),
     // security(
     //     ("bearer_auth" = [])
@@ -142,106 +282,64 @@ pub async fn get_post(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejection>
 )]
 pub async fn create_post(
     db: &SqlitePool,
+    blobs: &Blobs,
+    config: &Config,
     data: CreatePostRequest,
+    username: String,
     clients: Clients,
 ) -> Result<impl Reply, Rejection> {
+    let user = get_current_user(db, &username).await?;
 
+    let (mut file_data, reserved) =
+        request_files(db, &user, &data.uploaded_files, &data.upload_ids, config.uploads.create_post()).await?;
+    scan_files(blobs, &mut file_data).await?;
 
-    let file_data = match &data.uploaded_files {
-        Some(files) => {
-            let mut file_data = Vec::new();
-
-            for file in files {
-                let decoded = decode(file);
-                match decoded {
-                    Ok(decoded) => file_data.push(decoded),
-                    Err(_) => {
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                            warp::http::StatusCode::BAD_REQUEST,
-                        ));
-                    }
-                }
-            }
-
-            Some(file_data)
-        }
-        None => None,
-    };
-
+    let mut tx = ImmediateTx::begin(db).await?;
     if let Some(file_data) = &file_data {
-        for file in file_data {
-            if check_file_size(file).is_none() || check_file_size(file).is_none() {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                    warp::http::StatusCode::BAD_REQUEST,
-                ));
-            }
-        }
+        ensure_quota(tx.conn(), &config.quotas, &user, total_size(file_data), reserved).await?;
     }
 
     let query = "
-        INSERT INTO posts (title, content, author_id)
-        VALUES (?, ?, ?)
+        INSERT INTO posts (title, content, author_id, created_by)
+        VALUES (?, ?, ?, ?)
+        RETURNING id, title, content, author_id, created_at, updated_at, created_by
     ";
 
-    let result = sqlx::query(query)
+    // The row as stored, with the timestamps the database filled in
+    let post: Post = sqlx::query_as(query)
         .bind(&data.title)
         .bind(&data.content)
-        .bind(&data.author_id)
-        .execute(db)
-        .await;
-
-    if let Ok(row) = result {
-        let post_id = row.last_insert_rowid();
-
-
-        if let Some(file_data) = &file_data {
-            for file in file_data {
-                
-                let query = "
-                    INSERT INTO files (file, post_id)
-                    VALUES (?, ?)
-                ";
-
-                let file_result = sqlx::query(query)
-                    .bind(&file)
-                    .bind(&post_id)
-                    .execute(db)
-                    .await;
-
-                if let Err(_) = file_result {
-                    // Handle the error if file insertion fails.
-                    // You can return an error response or log the error here.
-                }
-            }
-        }
-
-        let post = Post {
-            id: post_id,
-            title: data.title.clone(),
-            content: data.content.clone(),
-            author_id: data.author_id.clone(),
-            created_at: Default::default(),
-            updated_at: Default::default(),
-        };
+        .bind(data.author_id)
+        .bind(user.id)
+        .fetch_one(tx.conn())
+        .await
+        .map_err(ApiError::from)?;
 
-        send_message_to_clients("Post has been created".to_string(), &clients).await;
+    let post_id = post.id;
 
-        let response = warp::reply::json(&SingePostResponse {
-            status: "Success".to_string(),
-            data: post,
-        });
+    if let Some(upload_ids) = &data.upload_ids {
+        claim_uploads(tx.conn(), user.id, upload_ids).await?;
+    }
+    let stored = match &file_data {
+        Some(file_data) => insert_file_rows(tx.conn(), blobs, post_id, user.id, file_data).await?,
+        None => Vec::new(),
+    };
+    tx.commit().await?;
 
-        return Ok(warp::reply::with_status(
-            response,
-            warp::http::StatusCode::CREATED,
-        ));
+    if let Some(file_data) = &file_data {
+        store_files(db, blobs, &stored, file_data).await?;
     }
 
+    send_event_to_clients(Event::new(EventData::PostCreated(post.clone()), &username), &clients).await;
+
+    let response = warp::reply::json(&SingePostResponse {
+        status: "Success".to_string(),
+        data: post,
+    });
+
     Ok(warp::reply::with_status(
-        warp::reply::json(&ErrorResponse::new("Failed to create post".to_string())),
-        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        response,
+        warp::http::StatusCode::CREATED,
     ))
 }
 
@@ -260,7 +358,7 @@ pub async fn create_post(
 pub async fn get_posts_by_auth(db: &SqlitePool, author_id: i64) -> Result<impl Reply, Rejection>{
 
     let query = "
-        SELECT id, title, content, author_id, created_at, updated_at
+        SELECT id, title, content, author_id, created_at, updated_at, created_by
         FROM posts 
         WHERE author_id = ?
     ";
@@ -276,14 +374,11 @@ pub async fn get_posts_by_auth(db: &SqlitePool, author_id: i64) -> Result<impl R
                 status: "Success".to_string(),
                 posts,
                 results: length,
+                pagination: None,
             };
             Ok(warp::reply::json(&response))
         }
-        Err(e) => {
-            eprintln!("Error fetching post: {:?}", e);
-            let error_response = ErrorResponse::new("Error fetching post".to_string());
-            Err(warp::reject::custom(error_response))
-        }
+        Err(e) => Err(ApiError::from(e).into()),
     }
 }
 
@@ -295,47 +390,39 @@ pub async fn get_posts_by_auth(db: &SqlitePool, author_id: i64) -> Result<impl R
     path = "/api/posts/{id}",  // Specify the path with {id} placeholder
     responses(
         (status = 201, description = "Post updated successfully", body = SingePostResponse),
-        (status = 400, description = "Bad Request"),
+        (status = 400, description = "Bad Request", body = ErrorBody),
+        (status = 403, description = "Only the owner or an admin can change this post", body = ErrorBody),
+        (status = NOT_FOUND, description = "Post was not found", body = ErrorBody),
+        (status = 413, description = "An uploaded file is too large", body = ErrorBody),
+        (status = 415, description = "An uploaded file's type is not accepted", body = ErrorBody),
+        (status = 422, description = "An upload id is unknown or unfinished", body = ErrorBody),
+        (status = 507, description = "The files don't fit in the user's storage quota", body = ErrorBody),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorBody),
     ),
     security(
         ("bearer_auth" = [])
     )
 )]
-pub async fn update_post(db: &SqlitePool, data:UpdatePostRequest, post_id: i64, clients: Clients)-> Result<impl Reply, Rejection>{
-   
-    let file_data = match &data.uploaded_files {
-        Some(files) => {
-            let mut file_data = Vec::new();
-
-            for file in files {
-                let decoded = decode(file);
-                match decoded {
-                    Ok(decoded) => file_data.push(decoded),
-                    Err(_) => {
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                            warp::http::StatusCode::BAD_REQUEST,
-                        ));
-                    }
-                }
-            }
-
-            Some(file_data)
+pub async fn update_post(db: &SqlitePool, blobs: &Blobs, config: &Config, data:UpdatePostRequest, post_id: i64, username: String, clients: Clients)-> Result<impl Reply, Rejection>{
+    authorize_post_change(db, post_id, &username).await?;
+
+    let user = get_current_user(db, &username).await?;
+    let (mut file_data, reserved) =
+        request_files(db, &user, &data.uploaded_files, &data.upload_ids, config.uploads.update_post()).await?;
+    scan_files(blobs, &mut file_data).await?;
+
+    let mut tx = ImmediateTx::begin(db).await?;
+    // New files replace the post's old ones. Those rows go first, so their
+    // space is already given back when the quota is checked
+    let replaced = match &file_data {
+        Some(file_data) => {
+            let replaced = delete_file_rows(tx.conn(), post_id).await?;
+            ensure_quota(tx.conn(), &config.quotas, &user, total_size(file_data), reserved).await?;
+            Some(replaced)
         }
         None => None,
     };
 
-    if let Some(file_data) = &file_data {
-        for file in file_data {
-            if check_file_size(file).is_none() || check_file_size(file).is_none() {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&ErrorResponse::new("Bad Request".to_string())),
-                    warp::http::StatusCode::BAD_REQUEST,
-                ));
-            }
-        }
-    }
-
     
     let query = "
     UPDATE posts
@@ -343,69 +430,158 @@ pub async fn update_post(db: &SqlitePool, data:UpdatePostRequest, post_id: i64,
     content = ?,
     updated_at = ?
     WHERE id = ?
+    RETURNING id, title, content, author_id, created_at, updated_at, created_by
     ";
 
     let updated_at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
     
 
-    _ = sqlx::query(query)
+    let post: Post = sqlx::query_as(query)
         .bind(&data.title)
         .bind(&data.content)
         .bind(&updated_at)
         .bind(post_id)
-        .execute(db)
-        .await;
+        .fetch_one(tx.conn())
+        .await
+        .map_err(ApiError::from)?;
+
+    if let Some(upload_ids) = &data.upload_ids {
+        claim_uploads(tx.conn(), user.id, upload_ids).await?;
+    }
+    let stored = match &file_data {
+        Some(file_data) => insert_file_rows(tx.conn(), blobs, post_id, user.id, file_data).await?,
+        None => Vec::new(),
+    };
+    tx.commit().await?;
 
+    if let Some(replaced) = replaced {
+        release_files(blobs, replaced).await?;
+    }
     if let Some(file_data) = &file_data {
-        let _ = sqlx::query("DELETE FROM files WHERE post_id = ?")
-        .bind(post_id)
-        .execute(db)
-        .await;
+        store_files(db, blobs, &stored, file_data).await?;
+    }
 
+    send_event_to_clients(Event::new(EventData::PostUpdated(post.clone()), &username), &clients).await;
 
+    let response = warp::reply::json(&SingePostResponse {
+        status: "Success".to_string(),
+        data: post,
+    });
 
-        for file in file_data {
-            
-            let query = "
-                INSERT INTO files (file, post_id)
-                VALUES (?, ?)
-            ";
+    Ok(warp::reply::with_status(
+        response,
+        warp::http::StatusCode::CREATED,
+    ))
 
-            let file_result = sqlx::query(query)
-                .bind(&file)
-                .bind(&post_id)
-                .execute(db)
-                .await;
 
-            if let Err(_) = file_result {
-                // Handle the error if file insertion fails.
-                // You can return an error response or log the error here.
-            }
+}
+
+
+
+//ATTACH FILES TO A POST FROM A multipart/form-data BODY
+#[utoipa::path(
+    post,
+    path = "/api/posts/{id}/files",
+    request_body(content = UploadFilesForm, content_type = "multipart/form-data", description = "One \"file\" part per file, each at most 10 MB"),
+    responses(
+        (status = 201, description = "Files attached to the post", body = UploadResponse),
+        (status = 400, description = "Malformed form or no file parts", body = ErrorBody),
+        (status = 403, description = "Only the owner or an admin can change this post", body = ErrorBody),
+        (status = NOT_FOUND, description = "Post was not found", body = ErrorBody),
+        (status = 411, description = "Content-Length header is missing", body = ErrorBody),
+        (status = 413, description = "A file or the whole request is too large", body = ErrorBody),
+        (status = 415, description = "A file's type is not accepted", body = ErrorBody),
+        (status = 507, description = "The files don't fit in the user's storage quota", body = ErrorBody),
+        (status = 503, description = "The malware scanner is unavailable", body = ErrorBody),
+    ),
+    params(
+        ("id" = u64, Path, description = "Post id"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn upload_post_files(db: &SqlitePool, blobs: &Blobs, config: &Config, post_id: i64, username: String, mut form: FormData, clients: Clients) -> Result<impl Reply, Rejection> {
+    // Check permissions before reading any of the body
+    let (author_id, _) = authorize_post_change(db, post_id, &username).await?;
+    let user = get_current_user(db, &username).await?;
+
+    let mut file_data = Vec::new();
+    while let Some(part) = next_part(&mut form).await? {
+        if part.name() != "file" {
+            return Err(ApiError::BadRequest(format!("Unexpected form field {:?}, send files as \"file\" parts", part.name())).into());
         }
+        let filename = part.filename().map(str::to_string);
+        let data = read_part(part, MAX_FILE_SIZE, "Uploaded files must not be larger than 10 MB").await?;
+        let file = NewFile::new(filename.as_deref(), data);
+        // Rejected before the rest of the body is read
+        file.check_allowed(config.uploads.post_files())?;
+        file_data.push(file);
     }
 
-    let post = Post {
-        id: Default::default(),
-        title: data.title.clone(),
-        content: data.content.clone(),
-        author_id: Default::default(),
-        created_at: Default::default(),
-        updated_at: updated_at,
-    };
+    if file_data.is_empty() {
+        return Err(ApiError::BadRequest("The form has no \"file\" parts".to_string()).into());
+    }
 
-    send_message_to_clients("Post has been updated".to_string(), &clients).await;
+    for file in &mut file_data {
+        file.scan(blobs.scanner()).await?;
+    }
 
-    let response = warp::reply::json(&SingePostResponse {
+    let mut tx = ImmediateTx::begin(db).await?;
+    ensure_quota(tx.conn(), &config.quotas, &user, total_size(&file_data), 0).await?;
+    let files = insert_file_rows(tx.conn(), blobs, post_id, user.id, &file_data).await?;
+    tx.commit().await?;
+    store_files(db, blobs, &files, &file_data).await?;
+
+    send_event_to_clients(Event::new(EventData::PostFilesAdded { post_id, author_id, files: files.clone() }, &username), &clients).await;
+
+    let response = warp::reply::json(&UploadResponse {
         status: "Success".to_string(),
-        data: post,
+        uploaded: files.len(),
+        files,
     });
 
     Ok(warp::reply::with_status(
         response,
         warp::http::StatusCode::CREATED,
     ))
+}
+
+
+
+//LIST THE FILES ATTACHED TO A POST
+#[utoipa::path(
+    get,
+    path = "/api/posts/{id}/files",
+    responses(
+        (status = 200, description = "Metadata of the post's files", body = FileListResponse),
+        (status = NOT_FOUND, description = "Post was not found", body = ErrorBody)
+    ),
+    params(
+        ("id" = u64, Path, description = "Post id"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_post_files(db: &SqlitePool, post_id: i64) -> Result<impl Reply, Rejection> {
+    let post: Option<(i64,)> = sqlx::query_as("SELECT id FROM posts WHERE id = ?")
+        .bind(post_id)
+        .fetch_optional(db)
+        .await
+        .map_err(ApiError::from)?;
 
+    if post.is_none() {
+        return Err(ApiError::NotFound("Post not found".to_string()).into());
+    }
 
+    let files = list_files(db, post_id).await?;
+
+    Ok(warp::reply::json(&FileListResponse {
+        status: "Success".to_string(),
+        results: files.len(),
+        files,
+    }))
 }
 
 
@@ -416,7 +592,8 @@ pub async fn update_post(db: &SqlitePool, data:UpdatePostRequest, post_id: i64,
     path = "/api/posts/{id}",
     responses(
         (status = 200, description = "Post deleted succescully"),
-        (status = NOT_FOUND, description = "Post was not found")
+        (status = 403, description = "Only the owner or an admin can delete this post", body = ErrorBody),
+        (status = NOT_FOUND, description = "Post was not found", body = ErrorBody)
     ),
     params(
         ("id" = u64, Path, description = "Post id"),
@@ -425,23 +602,35 @@ pub async fn update_post(db: &SqlitePool, data:UpdatePostRequest, post_id: i64,
         ("bearer_auth" = [])
     )
 )]
-pub async fn delete_post(db: &SqlitePool, id: i64, clients: Clients)-> Result<impl Reply, Rejection>{
+pub async fn delete_post(db: &SqlitePool, blobs: &Blobs, id: i64, username: String, clients: Clients)-> Result<impl Reply, Rejection>{
+    authorize_post_change(db, id, &username).await?;
+
+    // Sent along with the event
+    let post: Post = sqlx::query_as("SELECT id, title, content, author_id, created_at, updated_at, created_by FROM posts WHERE id = ?")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .map_err(ApiError::from)?;
 
     let query = "
         DELETE FROM posts
         where id = ?
     ";
 
-    _ = sqlx::query(query)
+    // Attachments reference the post, so they have to go first
+    delete_post_files(db, blobs, id).await?;
+
+    sqlx::query(query)
     .bind(id)
     .execute(db)
-    .await;
+    .await
+    .map_err(ApiError::from)?;
 
     let response = warp::reply::json(&StatusResponse {
         status: "Success".to_string(),
 
     });
-    send_message_to_clients("Post has been deleted".to_string(), &clients).await;
+    send_event_to_clients(Event::new(EventData::PostDeleted(post), &username), &clients).await;
 
     Ok(warp::reply::with_status(
         response,
@@ -462,39 +651,23 @@ pub async fn delete_post(db: &SqlitePool, id: i64, clients: Clients)-> Result<im
         ("id" = u64, Path, description = "Post id"),
     )
 )]
-pub async fn download_files_by_id(db: &SqlitePool, id: i64) -> Result<impl Reply, Rejection> {
-    let query = "
-        SELECT file
-        FROM files 
-        WHERE post_id = ?
-    ";
-
-    match sqlx::query(query)
-        .bind(id)
-        .fetch_all(db)
-        .await
-    {
-        Ok(rows) => {
-            let mut file_data_base64 = Vec::new();
-
-            for row in rows {
-                let file_data: Vec<u8> = row.get("file");
-                let file_data_base64_single = base64::encode(&file_data);
-                file_data_base64.push(file_data_base64_single);
-            }
+pub async fn download_files_by_id(db: &SqlitePool, blobs: &Blobs, id: i64) -> Result<impl Reply, Rejection> {
+    let files = list_files(db, id).await?;
+
+    let mut file_data_base64 = Vec::new();
+    // Quarantined files are left out, they are never handed out
+    for file in files.into_iter().filter(|file| !file.is_quarantined()) {
+        let file_data = blobs
+            .read_all(&file.storage, BlobOwner::File(file.id), file.sha256.as_deref().unwrap_or_default())
+            .await?;
+        file_data_base64.push(base64::encode(&file_data));
+    }
 
-            let response_data = FileResponse {
-                // Other fields of your response
-                status: "success".to_string(),
-                data: file_data_base64,
-            };
+    let response_data = FileResponse {
+        // Other fields of your response
+        status: "success".to_string(),
+        data: file_data_base64,
+    };
 
-            Ok(warp::reply::json(&response_data))
-        }
-        Err(e) => {
-            eprintln!("Error fetching file data: {:?}", e);
-            let error_response = ErrorResponse::new("Error fetching file data".to_string());
-            Err(warp::reject::custom(error_response))
-        }
-    }
+    Ok(warp::reply::json(&response_data))
 }
\ No newline at end of file
@@ -0,0 +1,328 @@
+use base64::Engine;
+use bytes::Buf;
+use futures::{pin_mut, Stream, StreamExt};
+use sqlx::SqlitePool;
+use warp::http::header::{HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Rejection, Reply};
+
+use crate::config::{QuotaConfig, UploadsConfig};
+use crate::db::quotas::ensure_quota;
+use crate::db::uploads::{append_chunk, create_upload, delete_upload, get_upload, Upload};
+use crate::db::ImmediateTx;
+use crate::errors::ApiError;
+use crate::Middleware::auth::get_current_user;
+use crate::Middleware::mime_check::MAX_FILE_SIZE;
+
+//RESUMABLE UPLOADS, tus 1.0.0 WITH THE creation, expiration AND termination EXTENSIONS
+//
+// A client creates an upload with its length, sends the bytes in as many
+// PATCH requests as it takes and asks with HEAD where to carry on after a
+// broken connection. Finished uploads are attached to a post by listing
+// their ids in upload_ids when creating or updating it
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+const PATCH_CONTENT_TYPE: &str = "application/offset+octet-stream";
+// Received bytes are saved in pieces of this size, so a connection that
+// breaks halfway keeps most of what it sent
+const SAVE_EVERY: usize = 256 * 1024;
+const MAX_METADATA_LEN: usize = 4 * 1024;
+
+//WHAT THIS SERVER SUPPORTS
+#[utoipa::path(
+    options,
+    path = "/api/uploads",
+    responses(
+        (status = 204, description = "Tus-Version, Tus-Extension and Tus-Max-Size headers"),
+    )
+)]
+pub async fn tus_options() -> Result<impl Reply, Rejection> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    headers.insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+    headers.insert("tus-extension", HeaderValue::from_static(TUS_EXTENSIONS));
+    headers.insert("tus-max-size", HeaderValue::from(MAX_FILE_SIZE));
+    Ok(response)
+}
+
+//START A RESUMABLE UPLOAD
+#[utoipa::path(
+    post,
+    path = "/api/uploads",
+    responses(
+        (status = 201, description = "Upload created, its URL is in Location"),
+        (status = 400, description = "Upload-Length or Upload-Metadata is missing or malformed", body = ErrorBody),
+        (status = 412, description = "Tus-Resumable is not 1.0.0", body = ErrorBody),
+        (status = 413, description = "Upload-Length is over the file size limit", body = ErrorBody),
+        (status = 507, description = "Upload-Length doesn't fit in the user's storage quota", body = ErrorBody),
+    ),
+    params(
+        ("Tus-Resumable" = String, Header, description = "1.0.0"),
+        ("Upload-Length" = u64, Header, description = "Size of the whole file in bytes"),
+        ("Upload-Metadata" = Option<String>, Header, description = "Comma separated key and base64 value pairs, filename is kept"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn create_tus_upload(
+    db: &SqlitePool,
+    uploads: &UploadsConfig,
+    quotas: &QuotaConfig,
+    username: String,
+    headers: HeaderMap,
+) -> Result<impl Reply, Rejection> {
+    check_tus_version(&headers)?;
+    let user = get_current_user(db, &username).await?;
+
+    if headers.contains_key("upload-defer-length") {
+        return Err(ApiError::BadRequest("Upload-Defer-Length is not supported, send Upload-Length".to_string()).into());
+    }
+    let length: u64 = header_number(&headers, "upload-length")?
+        .ok_or_else(|| ApiError::BadRequest("Upload-Length header is required".to_string()))?;
+    if length > MAX_FILE_SIZE as u64 {
+        return Err(ApiError::PayloadTooLarge("Uploaded files must not be larger than 10 MB".to_string()).into());
+    }
+    let metadata = header_str(&headers, "upload-metadata")?.map(str::to_string);
+    let filename = match &metadata {
+        Some(metadata) => metadata_filename(metadata)?,
+        None => None,
+    };
+
+    // The whole length is held from the start, so a quota can't be overrun by
+    // many uploads running at once
+    let mut tx = ImmediateTx::begin(db).await?;
+    ensure_quota(tx.conn(), quotas, &user, length as i64, 0).await?;
+    let upload = create_upload(tx.conn(), user.id, filename, metadata, length as i64, uploads.resumable_expiry_hours).await?;
+    tx.commit().await?;
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::CREATED;
+    let headers = response.headers_mut();
+    headers.insert(LOCATION, header_value(&format!("/api/uploads/{}", upload.id))?);
+    insert_expires(headers, &upload)?;
+    Ok(response)
+}
+
+//HOW MUCH OF AN UPLOAD HAS ARRIVED
+#[utoipa::path(
+    head,
+    path = "/api/uploads/{id}",
+    responses(
+        (status = 200, description = "Upload-Offset and Upload-Length of the upload"),
+        (status = NOT_FOUND, description = "Upload was not found"),
+        (status = 410, description = "Upload has expired"),
+    ),
+    params(
+        ("id" = String, Path, description = "Upload id"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn head_tus_upload(db: &SqlitePool, id: String, username: String, headers: HeaderMap) -> Result<impl Reply, Rejection> {
+    check_tus_version(&headers)?;
+    let user = get_current_user(db, &username).await?;
+    let upload = get_upload(db, &id, user.id).await?;
+
+    let mut response = Response::new(Body::empty());
+    let headers = response.headers_mut();
+    headers.insert("upload-offset", HeaderValue::from(upload.received));
+    headers.insert("upload-length", HeaderValue::from(upload.length));
+    if let Some(metadata) = &upload.metadata {
+        headers.insert("upload-metadata", header_value(metadata)?);
+    }
+    // The offset changes with every PATCH, a cached one would be wrong
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    insert_expires(headers, &upload)?;
+    Ok(response)
+}
+
+//ADD BYTES TO AN UPLOAD, STARTING AT Upload-Offset
+#[utoipa::path(
+    patch,
+    path = "/api/uploads/{id}",
+    request_body(content = Vec<u8>, content_type = "application/offset+octet-stream", description = "The next bytes of the file"),
+    responses(
+        (status = 204, description = "Bytes saved, Upload-Offset is the new offset"),
+        (status = 400, description = "Upload-Offset is missing or malformed", body = ErrorBody),
+        (status = NOT_FOUND, description = "Upload was not found", body = ErrorBody),
+        (status = 409, description = "Upload-Offset is not where the upload ends", body = ErrorBody),
+        (status = 410, description = "Upload has expired", body = ErrorBody),
+        (status = 412, description = "Tus-Resumable is not 1.0.0", body = ErrorBody),
+        (status = 413, description = "More bytes than Upload-Length", body = ErrorBody),
+        (status = 415, description = "Content-Type is not application/offset+octet-stream", body = ErrorBody),
+    ),
+    params(
+        ("id" = String, Path, description = "Upload id"),
+        ("Tus-Resumable" = String, Header, description = "1.0.0"),
+        ("Upload-Offset" = u64, Header, description = "Where these bytes start, the offset HEAD returned"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn patch_tus_upload<S, B>(
+    db: &SqlitePool,
+    uploads: &UploadsConfig,
+    id: String,
+    username: String,
+    headers: HeaderMap,
+    body: S,
+) -> Result<impl Reply, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    check_tus_version(&headers)?;
+    if header_str(&headers, CONTENT_TYPE.as_str())? != Some(PATCH_CONTENT_TYPE) {
+        return Err(ApiError::UnsupportedMediaType(format!("Content-Type must be {}", PATCH_CONTENT_TYPE)).into());
+    }
+    let offset: i64 = header_number(&headers, "upload-offset")?
+        .ok_or_else(|| ApiError::BadRequest("Upload-Offset header is required".to_string()))?;
+
+    let user = get_current_user(db, &username).await?;
+    let mut upload = get_upload(db, &id, user.id).await?;
+    if offset != upload.received {
+        return Err(ApiError::Conflict(format!("Upload-Offset must be {}, the bytes received so far", upload.received)).into());
+    }
+
+    let remaining = upload.length - upload.received;
+    let too_large = || ApiError::PayloadTooLarge(format!("Only {} more bytes fit in this upload", remaining));
+    if let Some(content_length) = header_number::<i64>(&headers, CONTENT_LENGTH.as_str())? {
+        if content_length > remaining {
+            return Err(too_large().into());
+        }
+    }
+
+    let expiry_hours = uploads.resumable_expiry_hours;
+    let mut pending = Vec::new();
+    pin_mut!(body);
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Keep what did arrive, the client resumes from there
+                append_chunk(db, &mut upload, &pending, expiry_hours).await?;
+                return Err(ApiError::BadRequest(format!("Upload body was cut off: {}", e)).into());
+            }
+        };
+
+        if (upload.received + (pending.len() + chunk.remaining()) as i64) > upload.length {
+            return Err(too_large().into());
+        }
+        while chunk.has_remaining() {
+            let piece = chunk.chunk();
+            let piece_len = piece.len();
+            pending.extend_from_slice(piece);
+            chunk.advance(piece_len);
+        }
+
+        if pending.len() >= SAVE_EVERY {
+            append_chunk(db, &mut upload, &pending, expiry_hours).await?;
+            pending.clear();
+        }
+    }
+    append_chunk(db, &mut upload, &pending, expiry_hours).await?;
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    headers.insert("upload-offset", HeaderValue::from(upload.received));
+    insert_expires(headers, &upload)?;
+    Ok(response)
+}
+
+//CANCEL AN UPLOAD AND DROP WHAT WAS RECEIVED
+#[utoipa::path(
+    delete,
+    path = "/api/uploads/{id}",
+    responses(
+        (status = 204, description = "Upload deleted"),
+        (status = NOT_FOUND, description = "Upload was not found", body = ErrorBody),
+        (status = 410, description = "Upload has expired", body = ErrorBody),
+    ),
+    params(
+        ("id" = String, Path, description = "Upload id"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn delete_tus_upload(db: &SqlitePool, id: String, username: String, headers: HeaderMap) -> Result<impl Reply, Rejection> {
+    check_tus_version(&headers)?;
+    let user = get_current_user(db, &username).await?;
+    let upload = get_upload(db, &id, user.id).await?;
+
+    delete_upload(db, &upload.id).await?;
+    Ok(warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT))
+}
+
+// Every request but OPTIONS names the protocol version it speaks
+fn check_tus_version(headers: &HeaderMap) -> Result<(), ApiError> {
+    match header_str(headers, "tus-resumable")? {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(ApiError::PreconditionFailed(format!("Tus-Resumable must be {}", TUS_VERSION))),
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>, ApiError> {
+    match headers.get(name) {
+        Some(value) => value
+            .to_str()
+            .map(|value| Some(value.trim()))
+            .map_err(|_| ApiError::BadRequest(format!("{} header is not valid text", name))),
+        None => Ok(None),
+    }
+}
+
+fn header_number<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Result<Option<T>, ApiError> {
+    match header_str(headers, name)? {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ApiError::BadRequest(format!("{} must be a non-negative number", name))),
+        None => Ok(None),
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, ApiError> {
+    HeaderValue::from_str(value).map_err(|_| ApiError::Internal(format!("Invalid header value {:?}", value)))
+}
+
+// Upload-Expires, as an HTTP date
+fn insert_expires(headers: &mut HeaderMap, upload: &Upload) -> Result<(), ApiError> {
+    if let Some(expires_at) = upload.expires_at() {
+        headers.insert("upload-expires", header_value(&expires_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string())?);
+    }
+    Ok(())
+}
+
+// Upload-Metadata is "key base64value,key base64value". The filename is taken
+// from "filename", or "name" as some clients send it
+fn metadata_filename(metadata: &str) -> Result<Option<String>, ApiError> {
+    if metadata.len() > MAX_METADATA_LEN {
+        return Err(ApiError::BadRequest(format!("Upload-Metadata must not be longer than {} bytes", MAX_METADATA_LEN)));
+    }
+
+    let mut filename = None;
+    for pair in metadata.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        if !matches!(key, "filename" | "name") || (key == "name" && filename.is_some()) {
+            continue;
+        }
+
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|value| String::from_utf8(value).ok())
+            .ok_or_else(|| ApiError::BadRequest(format!("Upload-Metadata {} is not base64 encoded UTF-8", key)))?;
+        filename = Some(decoded);
+    }
+
+    Ok(filename)
+}
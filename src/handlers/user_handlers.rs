@@ -1,26 +1,21 @@
 
-use crate::handlers::posts_handler::ErrorResponse;
-
-use crate::models::response::{UserRequest, StatusResponse, LoginResponse};
-use sha2::digest::generic_array::GenericArray;
-use sha2::{Sha256, Digest};
+use crate::models::response::{UserRequest, StatusResponse, LoginResponse, RefreshTokenRequest, UpdateRoleRequest, UpdateQuotaRequest, UsageResponse};
 use sqlx::{SqlitePool, FromRow};
 use warp::reply::with_status;
 use crate::Middleware::auth::*;
+use crate::Middleware::password::{hash_password, verify_password, Verification};
+use crate::config::{Config, QuotaConfig};
+use crate::db::quotas::storage_usage;
+use crate::errors::ApiError;
 use warp::{ Rejection, Reply};
 use warp::http::StatusCode;
 use warp::reply;
 
 
-use warp::reject::Reject;
-#[derive(Debug)]
-struct WrongCredentialsError;
-
-
-impl Reject for WrongCredentialsError {}
 #[derive(Debug, FromRow)]
 pub struct UserPassword {
     pub password: String,
+    pub role: String,
 }
 
 
@@ -29,28 +24,38 @@ pub struct UserPassword {
     request_body = UserRequest,
     path = "/api/register",
     responses(
-        (status = 200, description = "Registration successfull"),
-        (status = NOT_FOUND, description = "Basic auth required")
+        (status = 201, description = "Registration successfull"),
+        (status = 409, description = "Username is already taken", body = ErrorBody),
+        (status = 422, description = "Username or password is empty", body = ErrorBody)
     ),
 )]
 pub async fn register_user_handler(db: &SqlitePool, data:UserRequest) -> Result<impl Reply, Rejection> {
-    let mut hasher = Sha256::new();
-    
-    
-    hasher.update(&data.password.as_bytes());
-    let hashed_result = hasher.finalize();
+    if data.username.trim().is_empty() || data.password.is_empty() {
+        return Err(ApiError::Validation("Username and password are required".to_string()).into());
+    }
+
+    let existing = sqlx::query("SELECT id FROM users WHERE username = ?")
+        .bind(&data.username)
+        .fetch_optional(db)
+        .await
+        .map_err(ApiError::from)?;
+    if existing.is_some() {
+        return Err(ApiError::Conflict("Username is already taken".to_string()).into());
+    }
+
+    let hashed_password = hash_password(data.password.clone()).await?;
     
     let query = "
         INSERT INTO users (username, password)
         VALUES (?, ?)
     ";
 
-    println!("{:?}", &hashed_result);
-    _ = sqlx::query(query)
+    sqlx::query(query)
         .bind(&data.username)
-        .bind(hex::encode(hashed_result))
+        .bind(&hashed_password)
         .execute(db)
-        .await;
+        .await
+        .map_err(ApiError::from)?;
 
 
 
@@ -66,22 +71,17 @@ pub async fn register_user_handler(db: &SqlitePool, data:UserRequest) -> Result<
 }
 
 
-async fn get_users_hash(db: &SqlitePool, username:String) -> Result<String, sqlx::Error> {
+async fn get_users_hash(db: &SqlitePool, username:String) -> Result<UserPassword, sqlx::Error> {
     let query = "
-    SELECT password
+    SELECT password, role
     FROM users
     WHERE username = (?)
     ";
 
-    let res: Result<UserPassword, sqlx::Error> = sqlx::query_as(query)
+    sqlx::query_as(query)
     .bind(username)
     .fetch_one(db)
-    .await;
-
-    match res {
-        Ok(user_password) => Ok(user_password.password),
-        Err(err) => Err(err),
-    }
+    .await
 }
 
 
@@ -93,52 +93,199 @@ async fn get_users_hash(db: &SqlitePool, username:String) -> Result<String, sqlx
     path = "/api/login",
     responses(
         (status = 200, description = "Login using credentials. Returns bearer token.", body = LoginResponse),
-        (status = NOT_FOUND, description = "Basic auth required")
+        (status = 401, description = "Invalid credentials", body = ErrorBody)
     ),
     security(
         ("basic_auth" = [])
     )
 )]
-pub async fn login_user_handler(db: &SqlitePool, data:UserRequest) -> Result<impl Reply, Rejection> {
+pub async fn login_user_handler(db: &SqlitePool, config: &Config, data:UserRequest) -> Result<impl Reply, Rejection> {
     let username = data.username;
     let form_password = data.password;
-    
-
 
     match get_users_hash(db, username.clone()).await {
-        Ok(db_password_hash) => {
-         
-            let mut hasher = Sha256::new();
-            hasher.update(form_password.as_bytes());
-            let provided_password_hash = hasher.finalize();
-            
-            let db_password_bytes = hex::decode(&db_password_hash);
-
-            match db_password_bytes {
-                Ok(db_password_bytes) => {
-                    let db_password_array = GenericArray::clone_from_slice(&db_password_bytes);
-                    // Compare the hashed passwords
-                    if provided_password_hash == db_password_array {
-                        
-                        let token = create_jwt(&username)?;
-                        let response = LoginResponse { token };
-                        Ok(with_status(reply::json(&response), StatusCode::OK))
-                    
-                    } else {
-                        // Passwords don't match
-                        return Err(warp::reject::custom(ErrorResponse::new("Invalid credentials".to_string())));
+        Ok(user) => {
+            match verify_password(form_password.clone(), user.password.clone()).await? {
+                Verification::Valid { needs_rehash } => {
+                    if needs_rehash {
+                        // Upgrade legacy or outdated hashes now that we know the plaintext
+                        rehash_password(db, &username, form_password).await;
                     }
+
+                    let role = user.role.parse().unwrap_or_default();
+                    let tokens = issue_tokens(db, &config.auth, &username, role).await?;
+                    let response = LoginResponse {
+                        token: tokens.access_token,
+                        refresh_token: tokens.refresh_token,
+                    };
+                    Ok(with_status(reply::json(&response), StatusCode::OK))
                 }
-                Err(_) => {
-                    // Handle the case where the hex string is not valid
-                    return Err(warp::reject::custom(ErrorResponse::new("Invalid credentials".to_string())));
+                Verification::Invalid => {
+                    // Passwords don't match
+                    Err(ApiError::Unauthorized("Invalid credentials".to_string()).into())
                 }
             }
         }
+        Err(sqlx::Error::RowNotFound) => {
+            // User not found in the database. Same answer as a wrong password so usernames can't be probed
+            Err(ApiError::Unauthorized("Invalid credentials".to_string()).into())
+        }
+        Err(e) => Err(ApiError::from(e).into()),
+    }
+}
+
+async fn rehash_password(db: &SqlitePool, username: &str, password: String) {
+    let new_hash = match hash_password(password).await {
+        Ok(new_hash) => new_hash,
         Err(_) => {
-            // User not found in the database
-            return Err(warp::reject::custom(ErrorResponse::new("User not found".to_string())));
+            eprintln!("Failed to rehash password for {}", username);
+            return;
         }
+    };
+
+    let query = "
+        UPDATE users
+        SET password = ?
+        WHERE username = ?
+    ";
+
+    if let Err(e) = sqlx::query(query)
+        .bind(&new_hash)
+        .bind(username)
+        .execute(db)
+        .await
+    {
+        eprintln!("Error upgrading password hash: {:?}", e);
+    }
+}
+
+
+#[utoipa::path(
+    post,
+    request_body = RefreshTokenRequest,
+    path = "/api/token/refresh",
+    responses(
+        (status = 200, description = "Returns a new bearer token and a new refresh token. The old refresh token can't be used again.", body = LoginResponse),
+        (status = 401, description = "Refresh token is invalid, expired or was already used", body = ErrorBody)
+    ),
+)]
+pub async fn refresh_token_handler(db: &SqlitePool, config: &Config, data: RefreshTokenRequest) -> Result<impl Reply, Rejection> {
+    let tokens = rotate_refresh_token(db, &config.auth, &data.refresh_token).await?;
+
+    let response = LoginResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+    };
+    Ok(with_status(reply::json(&response), StatusCode::OK))
+}
+
+
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    responses(
+        (status = 200, description = "Bearer token and its refresh tokens are revoked"),
+        (status = 401, description = "Bearer token is missing or invalid", body = ErrorBody)
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn logout_handler(db: &SqlitePool, claims: Claims) -> Result<impl Reply, Rejection> {
+    revoke_access_token(db, &claims).await?;
+    revoke_session(db, &claims.sid).await?;
+
+    let response = warp::reply::json(&StatusResponse {
+        status: "Success".to_string(),
+    });
+    Ok(with_status(response, StatusCode::OK))
+}
+
+
+#[utoipa::path(
+    patch,
+    request_body = UpdateRoleRequest,
+    path = "/api/users/{username}/role",
+    responses(
+        (status = 200, description = "Role changed. Takes effect on the user's next login or token refresh"),
+        (status = 403, description = "Only admins can change roles", body = ErrorBody),
+        (status = NOT_FOUND, description = "User was not found", body = ErrorBody)
+    ),
+    params(
+        ("username" = String, Path, description = "Username"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn update_role_handler(db: &SqlitePool, username: String, data: UpdateRoleRequest) -> Result<impl Reply, Rejection> {
+    set_user_role(db, &username, data.role).await?;
+
+    let response = warp::reply::json(&StatusResponse {
+        status: "Success".to_string(),
+    });
+    Ok(with_status(response, StatusCode::OK))
+}
+
+
+//STORAGE USED BY THE CALLER AND HOW MUCH IS LEFT
+#[utoipa::path(
+    get,
+    path = "/api/me/usage",
+    responses(
+        (status = 200, description = "Bytes used by attachments and unfinished uploads, and the quota", body = UsageResponse),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn usage_handler(db: &SqlitePool, quotas: &QuotaConfig, username: String) -> Result<impl Reply, Rejection> {
+    let user = get_current_user(db, &username).await?;
+    let usage = storage_usage(&mut *db.acquire().await.map_err(ApiError::from)?, quotas, &user).await?;
+
+    Ok(reply::json(&UsageResponse {
+        status: "Success".to_string(),
+        data: usage,
+    }))
+}
+
+
+#[utoipa::path(
+    patch,
+    request_body = UpdateQuotaRequest,
+    path = "/api/users/{username}/quota",
+    responses(
+        (status = 200, description = "Quota changed. Files already stored are kept even if they are over it"),
+        (status = 403, description = "Only admins can change quotas", body = ErrorBody),
+        (status = NOT_FOUND, description = "User was not found", body = ErrorBody),
+        (status = 422, description = "Quota is negative", body = ErrorBody)
+    ),
+    params(
+        ("username" = String, Path, description = "Username"),
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn update_quota_handler(db: &SqlitePool, username: String, data: UpdateQuotaRequest) -> Result<impl Reply, Rejection> {
+    if data.quota_bytes.is_some_and(|quota| quota < 0) {
+        return Err(ApiError::Validation("quota_bytes must not be negative".to_string()).into());
     }
+
+    let result = sqlx::query("UPDATE users SET storage_quota = ? WHERE username = ?")
+        .bind(data.quota_bytes)
+        .bind(&username)
+        .execute(db)
+        .await
+        .map_err(ApiError::from)?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("User not found".to_string()).into());
     }
 
+    let response = warp::reply::json(&StatusResponse {
+        status: "Success".to_string(),
+    });
+    Ok(with_status(response, StatusCode::OK))
+}
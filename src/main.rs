@@ -4,7 +4,7 @@ use crate::models::posts::Post;
 use crate::models::authors::Author;
 use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
 use utoipa::OpenApi;
-use utoipa_swagger_ui::Config;
+use utoipa_swagger_ui::Config as SwaggerConfig;
 use ws::clients::*;
 
 use swagger::{ApiDoc, serve_swagger};
@@ -16,10 +16,16 @@ mod models;
 mod handlers;
 mod ws;
 mod swagger;
+mod config;
+mod errors;
+mod storage;
+mod scanner;
 use crate::Middleware::mime_check::check_content_type;
 use crate::db::database;
-
-const DB_URL: &str = "sqlite://sqlite.db";
+use crate::config::Config;
+use crate::errors::{handle_rejection, ApiError};
+use crate::models::user::Role;
+use crate::storage::Blobs;
 
 async fn apply_migrations(db: &SqlitePool){
     let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
@@ -42,45 +48,159 @@ match migration_results {
 
 #[tokio::main]
 async fn main() {
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(error) => {
+            eprintln!("Invalid configuration: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let db_url = config.database.url.as_str();
+    let bind_address = config.bind_address().expect("validated on load");
+
     // Initialize your database connection pool
-    if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-        println!("Creating database {}", DB_URL);
-        match Sqlite::create_database(DB_URL).await {
+    if !Sqlite::database_exists(db_url).await.unwrap_or(false) {
+        println!("Creating database {}", db_url);
+        match Sqlite::create_database(db_url).await {
             Ok(_) => println!("Create db success"),
             Err(error) => panic!("error: {}", error),
         }
     } else {
         println!("Database already exists");
     }
-    let db_url = "sqlite://sqlite.db";
     let db = SqlitePool::connect(db_url)
         .await
         .expect("Failed to connect to the database");
 
-    let clients: Clients = create_clients();
+    let clients: Clients = create_clients(db.clone(), &config.events);
     
   
+    let ws_db = db.clone();
+    let ws_config = config.clone();
     let ws_route = warp::path("ws")
         .and(warp::ws())
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(warp::query::<ws::ws_handler::WsQuery>())
         .and(with_clients(clients.clone()))
+        .and(warp::any().map(move || ws_db.clone()))
+        .and(warp::any().map(move || ws_config.clone()))
         .and_then(ws::ws_handler::ws_handler);
     
     // // Apply migrations
     apply_migrations(&db).await;
 
+    // Files stored before checksums were recorded
+    match db::files::backfill_checksums(&db).await {
+        Ok(0) => {}
+        Ok(count) => println!("Backfilled checksums for {} files", count),
+        Err(error) => panic!("error: could not backfill file checksums: {:?}", error),
+    }
+
+    let blobs = Blobs::new(db.clone(), &config.storage, &config.scanning);
+
+    // Resumable uploads nobody finished or attached, and events past retention
+    let prune_db = db.clone();
+    let prune_config = config.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(15 * 60));
+        loop {
+            interval.tick().await;
+            match db::uploads::prune_expired_uploads(&prune_db).await {
+                Ok(0) => {}
+                Ok(count) => println!("Deleted {} expired uploads", count),
+                Err(error) => eprintln!("Pruning expired uploads failed: {:?}", error),
+            }
+            match db::events::prune_events(&prune_db, &prune_config.events).await {
+                Ok(0) => {}
+                Ok(count) => println!("Deleted {} old events", count),
+                Err(error) => eprintln!("Pruning events failed: {:?}", error),
+            }
+        }
+    });
+
+    // Someone has to be able to hand out roles on a fresh install. Only until
+    // there is an admin, from then on roles are theirs to change
+    let needs_admin = match Middleware::auth::has_admin(&db).await {
+        Ok(has_admin) => !has_admin,
+        Err(error) => panic!("error: could not look for an admin: {:?}", error),
+    };
+    if let (Some(username), true) = (&config.auth.initial_admin, needs_admin) {
+        match Middleware::auth::set_user_role(&db, username, Role::Admin).await {
+            Ok(()) => {}
+            Err(ApiError::NotFound(_)) => println!("auth.initial_admin {} has not registered yet", username),
+            Err(error) => panic!("error: could not make {} an admin: {:?}", username, error),
+        }
+    }
+
+    // `rest_api migrate-blobs` moves uploads out of the database and exits,
+    // `rest_api promote-admin <username>` makes a user an admin and exits
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("promote-admin") => {
+            let Some(username) = std::env::args().nth(2) else {
+                eprintln!("Usage: rest_api promote-admin <username>");
+                std::process::exit(2);
+            };
+            match Middleware::auth::set_user_role(&db, &username, Role::Admin).await {
+                Ok(()) => {
+                    println!("{} is now an admin, from their next login or token refresh", username);
+                    return;
+                }
+                Err(error) => {
+                    eprintln!("Could not make {} an admin: {:?}", username, error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("migrate-blobs") => {
+            match storage::migrate_to_filesystem(&db, &blobs).await {
+                Ok((files, photos)) => {
+                    println!("Moved {} files and {} author photos to {}", files, photos, config.storage.path.display());
+                    return;
+                }
+                Err(error) => {
+                    eprintln!("Blob migration failed: {:?}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(other) => {
+            eprintln!("Unknown command {:?}, usage: rest_api [migrate-blobs | promote-admin <username>]", other);
+            std::process::exit(2);
+        }
+    }
+
+    // Files stored before a scanner was configured
+    if blobs.scanner().is_enabled() {
+        let scan_db = db.clone();
+        let scan_blobs = blobs.clone();
+        tokio::spawn(async move {
+            match db::files::scan_unscanned_files(&scan_db, &scan_blobs).await {
+                Ok(0) => {}
+                Ok(count) => println!("Scanned {} files stored before scanning was enabled", count),
+                Err(error) => eprintln!("Scanning stored files failed: {:?}", error),
+            }
+        });
+    }
+
     
     
    
     let cors = warp::cors()
-        .allow_methods(&[Method::GET, Method::POST, Method::PATCH, Method::DELETE])
-        .allow_origins(vec!["http://localhost:3000", "http://localhost:8000"])
+        .allow_methods(&[Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_origins(config.server.cors_origins.iter().map(String::as_str))
         .allow_headers(vec!["*"])
+        .expose_headers(vec![
+            "link", "etag", "content-range", "content-disposition", "x-request-id", "location",
+            "tus-resumable", "tus-version", "tus-extension", "tus-max-size",
+            "upload-offset", "upload-length", "upload-metadata", "upload-expires",
+        ])
         .allow_credentials(true);
 
         
         let openapi = ApiDoc::openapi();
         
-        let config = Arc::new(Config::from("/api-doc.json"));
+        let swagger_config = Arc::new(SwaggerConfig::from("/api-doc.json"));
 
 
         // Serve Swagger UI
@@ -92,21 +212,31 @@ async fn main() {
             .and(warp::get())
             .and(warp::path::full())
             .and(warp::path::tail())
-            .and(warp::any().map(move || config.clone()))
+            .and(warp::any().map(move || swagger_config.clone()))
             .and_then(serve_swagger);
 
         
         
 
+        // Raw file downloads and the event stream are opened by browsers too, so they skip the JSON content type check.
+        // They come second so their errors win over the content type rejection
         let routes = check_content_type()
-            .and(database::routes(&db, clients).with(&cors))
+            .and(database::routes(&db, clients.clone(), config.clone(), blobs.clone()).with(&cors))
+            .or(database::upload_routes(&db, config.clone()).with(&cors))
+            .or(database::file_routes(&db, config.clone(), blobs).with(&cors))
+            .or(database::event_routes(&db, clients, config.clone()).with(&cors))
             .boxed()
-            .or(ws_route.with(warp::cors().allow_any_origin()));
+            .or(ws_route.with(&cors));
 
 
  
-    warp::serve(routes.or(api_doc.with(cors.clone())).or(swagger_ui.with(cors.clone())))
-        .run(([127, 0, 0, 1], 8000)) 
+    let routes = routes
+        .or(api_doc.with(cors.clone()))
+        .or(swagger_ui.with(cors.clone()))
+        .recover(handle_rejection);
+
+    warp::serve(routes)
+        .run(bind_address)
         .await;
 }
 
@@ -8,7 +8,24 @@ pub struct Author {
     pub id: i64,
     pub name: String,
     pub surname: String,
-    pub photo: Option<Vec<u8>>,
+    // GET /api/authors/{id}/photo when the author has a photo. The v parameter
+    // changes with the photo, so the URL can be cached
+    #[sqlx(skip)]
+    #[schema(example = "/api/authors/1/photo?v=9f86d081884c")]
+    pub photo_url: Option<String>,
     pub created_at: String,
     pub updated_at: String,
+    pub created_by: Option<i64>,
+    // Identifies the current photo, see storage::Blobs
+    #[serde(skip)]
+    pub photo_sha256: Option<String>,
+}
+
+impl Author {
+    pub fn set_photo_url(&mut self) {
+        self.photo_url = self
+            .photo_sha256
+            .as_ref()
+            .map(|sha256| format!("/api/authors/{}/photo?v={}", self.id, &sha256[..sha256.len().min(12)]));
+    }
 }
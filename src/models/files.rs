@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::scanner::ScanStatus;
+
+// What is known about a stored file, without its content
+#[derive(Clone, FromRow, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FileMetadata {
+    pub id: i64,
+    pub post_id: i64,
+    // Unknown for files uploaded as base64 or before filenames were recorded
+    #[schema(example = "report.pdf")]
+    pub filename: Option<String>,
+    #[schema(example = "application/pdf")]
+    pub mime_type: String,
+    pub size: i64,
+    #[schema(example = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")]
+    pub sha256: Option<String>,
+    pub uploaded_at: Option<String>,
+    // unscanned, clean or quarantined. Quarantined files can't be downloaded
+    #[schema(example = "clean")]
+    pub scan_status: String,
+    // The signature the malware scanner matched
+    #[schema(example = "Eicar-Test-Signature")]
+    pub scan_result: Option<String>,
+    // BlobStore holding the content
+    #[serde(skip)]
+    pub storage: String,
+}
+
+impl FileMetadata {
+    pub fn is_quarantined(&self) -> bool {
+        self.scan_status == ScanStatus::Quarantined.as_str()
+    }
+}
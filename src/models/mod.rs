@@ -1,4 +1,5 @@
 pub mod authors;
+pub mod files;
 pub mod posts;
 pub mod response;
-pub mod user;
\ No newline at end of file
+pub mod user;
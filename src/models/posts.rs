@@ -12,5 +12,6 @@ pub struct Post {
     pub author_id: i64,
     pub created_at: String,
     pub updated_at: String,
+    pub created_by: Option<i64>,
 }
 
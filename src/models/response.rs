@@ -1,6 +1,7 @@
-use crate::models::{authors::Author, posts::Post};
+use crate::models::{authors::Author, files::FileMetadata, posts::Post, user::Role};
 
 use serde::{Serialize, Deserialize};
+use sqlx::FromRow;
 use utoipa::ToSchema;
 
 #[derive(Serialize)]
@@ -29,7 +30,9 @@ pub struct SingePostResponse {
 pub struct AuthorResponse {
     pub status: String,
     pub results: usize,
-    pub authors: Vec<Author>
+    pub authors: Vec<Author>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<PageInfo>
 }
 
 
@@ -37,7 +40,47 @@ pub struct AuthorResponse {
 pub struct PostResponse {
     pub status: String,
     pub results: usize,
-    pub posts: Vec<Post>
+    pub posts: Vec<Post>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<PageInfo>
+}
+
+// A full text search hit, best matches first. The snippet is HTML escaped
+// text with matched terms in <mark></mark>; a lower score is a better match (bm25)
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct PostSearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub post: Post,
+    #[schema(example = "…the <mark>rust</mark> borrow checker…")]
+    pub snippet: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, Debug, FromRow, ToSchema)]
+pub struct AuthorSearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub author: Author,
+    #[schema(example = "<mark>John</mark> Doe")]
+    pub snippet: String,
+    pub score: f64,
+}
+
+#[derive(Serialize,Debug, ToSchema)]
+pub struct PostSearchResponse {
+    pub status: String,
+    pub results: usize,
+    pub posts: Vec<PostSearchHit>,
+    pub pagination: PageInfo
+}
+
+#[derive(Serialize,Debug, ToSchema)]
+pub struct AuthorSearchResponse {
+    pub status: String,
+    pub results: usize,
+    pub authors: Vec<AuthorSearchHit>,
+    pub pagination: PageInfo
 }
 
 #[derive(Deserialize, Debug, ToSchema)]
@@ -60,6 +103,9 @@ pub struct CreatePostRequest {
     pub author_id: i64,
     #[schema(example = "files in ['Base64', 'Base64'] format (Optional)", required = false)]
     pub uploaded_files: Option<Vec<String>>,
+    // Ids of finished resumable uploads from /api/uploads, attached like uploaded_files
+    #[schema(example = json!(["5f0c7e8a9b1d4c2e8f3a6b7c9d0e1f2a"]), required = false)]
+    pub upload_ids: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug, ToSchema)]
@@ -70,6 +116,12 @@ pub struct UserRequest {
     pub password: String
 }
 
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct UpdateRoleRequest {
+    #[schema(example = "editor", required = true)]
+    pub role: Role,
+}
+
 #[derive(Deserialize, Debug, ToSchema)]
 pub struct UpdateAuthorRequest{
     #[schema(example = "John", required = true)]
@@ -90,6 +142,9 @@ pub struct UpdatePostRequest{
     pub content: String,
     #[schema(example = "files in ['Base64', 'Base64'] format (Optional)", required = false)]
     pub uploaded_files: Option<Vec<String>>,
+    // Ids of finished resumable uploads from /api/uploads, attached like uploaded_files
+    #[schema(example = json!(["5f0c7e8a9b1d4c2e8f3a6b7c9d0e1f2a"]), required = false)]
+    pub upload_ids: Option<Vec<String>>,
 
 }
 
@@ -103,20 +158,160 @@ pub struct StatusResponse {
 #[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct RefreshTokenRequest {
+    #[schema(example = "Refresh token returned by login or a previous refresh", required = true)]
+    pub refresh_token: String,
 }
 
+// Paging, filtering and sorting options shared by the list endpoints,
+// validated against a whitelist in db::listing
 #[derive(Deserialize)]
 pub struct PageQueryParam {
     pub page: Option<u32>,
+    pub limit: Option<i64>,
+    // Comma separated columns, prefix with - for descending: -created_at,title
+    pub sort: Option<String>,
+    // Comma separated sparse fieldset: id,title
+    pub fields: Option<String>,
+    pub author_id: Option<i64>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    // Opaque cursors from a previous response's pagination.next / pagination.prev
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub total: Option<TotalMode>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TotalMode {
+    Exact,
+    Estimate,
+}
+
+impl TotalMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TotalMode::Exact => "exact",
+            TotalMode::Estimate => "estimate",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct PageInfo {
+    #[schema(example = 10)]
+    pub limit: i64,
+    // Pass as ?after= / ?before= to get the neighbouring page. Only set when
+    // the list is ordered by created_at
+    pub next: Option<String>,
+    pub prev: Option<String>,
+    // Only set when asked for with ?total=exact or ?total=estimate
+    pub total: Option<i64>,
+    pub total_estimated: bool,
+    pub has_next: bool,
+    pub has_prev: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SearchQueryParam{
+    pub q: Option<String>,
+    // Older name for q, still accepted
     pub search: Option<String>
 }
 
+impl SearchQueryParam {
+    pub fn term(self) -> Option<String> {
+        self.q.or(self.search).filter(|term| !term.trim().is_empty())
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UploadResponse {
+    pub status: String,
+    #[schema(example = 2)]
+    pub uploaded: usize,
+    pub files: Vec<FileMetadata>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FileListResponse {
+    pub status: String,
+    pub results: usize,
+    pub files: Vec<FileMetadata>,
+}
+
+// Documentation only: the multipart/form-data bodies the upload endpoints read
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct UploadFilesForm {
+    // Repeat the part for every file
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub file: Vec<Vec<u8>>,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct AuthorPhotoForm {
+    #[schema(value_type = String, format = Binary)]
+    pub photo: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadQueryParam {
+    // Ask the browser to save the file rather than display it
+    pub download: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQueryParam {
+    // For EventSource, which can't send an Authorization header
+    pub token: Option<String>,
+    // Comma separated, posts and authors when not given
+    pub topics: Option<String>,
+    // Seq of the last event received before reconnecting
+    pub since: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhotoQueryParam {
+    // Fit the photo in this box, keeping its aspect ratio
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+}
+
 #[derive (Debug, Serialize, ToSchema)]
 pub struct FileResponse{
     pub status: String,
     pub data: Vec<String>,
-}
\ No newline at end of file
+}
+
+// How much of their quota a user has taken
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StorageUsage {
+    // Attachments stored with posts
+    pub used_bytes: i64,
+    pub files: i64,
+    // Held for resumable uploads that are not attached to a post yet
+    pub pending_bytes: i64,
+    // None when there is no limit
+    pub quota_bytes: Option<i64>,
+    pub remaining_bytes: Option<i64>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct UsageResponse {
+    pub status: String,
+    pub data: StorageUsage,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct UpdateQuotaRequest {
+    // null gives the user the limit of their role again
+    #[schema(example = "1073741824", required = true)]
+    pub quota_bytes: Option<i64>,
+}
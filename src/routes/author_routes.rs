@@ -1,9 +1,17 @@
+use std::sync::Arc;
+use warp::http::{HeaderMap, Method};
+use warp::multipart::FormData;
 use warp::{Filter, Rejection, Reply};
 use crate::ws::clients::Clients;
+use crate::storage::Blobs;
 use sqlx::SqlitePool;
+use crate::config::Config;
 use crate::handlers::author_handler::*;
-use crate::Middleware::auth::with_auth;
-use crate::models::response::{CreateAuthorRequest, UpdateAuthorRequest, PageQueryParam, SearchQueryParam};
+use crate::Middleware::auth::{with_auth, with_role};
+use crate::Middleware::mime_check::MAX_IMAGE_SIZE;
+use crate::models::user::Role;
+use crate::models::response::{CreateAuthorRequest, UpdateAuthorRequest, PageQueryParam, SearchQueryParam, PhotoQueryParam};
+use crate::handlers::files_handler::get_author_photo;
 
 
 
@@ -19,7 +27,7 @@ pub fn get_authors(db: SqlitePool) -> impl Filter<Extract = impl warp::Reply, Er
          
 
             async move {
-                let search_param = search_query_params.search;
+                let search_param = search_query_params.term();
                 get_all_authors(page_query_param, search_param, &db_clone).await
             }
         })
@@ -27,9 +35,9 @@ pub fn get_authors(db: SqlitePool) -> impl Filter<Extract = impl warp::Reply, Er
 
 
 //ROUTE FOR SINGLE AUTHOR
-pub fn get_author_route(db: SqlitePool) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn get_author_route(db: SqlitePool, config: Arc<Config>) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "authors" / i64)
-        .and(with_auth())
+        .and(with_auth(db.clone(), config))
         .and(warp::get())
         .and_then(move |id: i64, _: String| {
             let db_clone = db.clone(); 
@@ -42,24 +50,28 @@ pub fn get_author_route(db: SqlitePool) -> impl Filter<Extract = impl warp::Repl
 //ROUTE TO POST AN AUTHOR
 pub fn post_author_route(
     pool: SqlitePool,
-    clients: Clients
+    config: Arc<Config>,
+    clients: Clients,
+    blobs: Blobs,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
 
     let clients_clone = clients.clone();
 
 
 
-    warp::post()
-        .and(warp::path!("api" / "authors"))
-        .and(with_auth())
+    warp::path!("api" / "authors")
+        .and(warp::post())
+        .and(with_role(pool.clone(), config.clone(), Role::Editor))
         .and(warp::body::json())
         .and(warp::any().map(move || pool.clone()))
-        .and_then(move|_: String, data: CreateAuthorRequest, db: SqlitePool| {
+        .and_then(move|username: String, data: CreateAuthorRequest, db: SqlitePool| {
                 let clients_clone = clients_clone.clone();
+                let blobs = blobs.clone();
+                let config = config.clone();
     
                 async move {
                     
-                    post_author(&db, data, clients_clone).await
+                    post_author(&db, &blobs, &config.photos, data, username, clients_clone).await
                 }
             })
 
@@ -69,21 +81,25 @@ pub fn post_author_route(
     //ROUTE TO UPDATE AN AUTHOR
 pub fn update_author_route(
     pool: SqlitePool,
-    clients: Clients
+    config: Arc<Config>,
+    clients: Clients,
+    blobs: Blobs,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
 
-    warp::patch()
-        .and(warp::path!("api" / "authors" / i64))
-        .and(with_auth())
+    warp::path!("api" / "authors" / i64)
+        .and(warp::patch())
+        .and(with_role(pool.clone(), config.clone(), Role::Editor))
         .and(warp::body::json())
         .and(warp::any().map(move || pool.clone()))
-        .and_then(move|id: i64, _: String, data: UpdateAuthorRequest, db: SqlitePool| {
+        .and_then(move|id: i64, username: String, data: UpdateAuthorRequest, db: SqlitePool| {
 
             let clients_clone = clients.clone();
+            let blobs = blobs.clone();
+            let config = config.clone();
 
             async move {
                 
-                let result = update_author(&db, data, id, clients_clone).await;
+                let result = update_author(&db, &blobs, &config.photos, data, id, username, clients_clone).await;
 
           
             match result {
@@ -98,24 +114,52 @@ pub fn update_author_route(
 }
 
 //DELTE AUTHOR ROUTE
-pub fn delete_author_route(db: SqlitePool, clients: Clients) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn delete_author_route(db: SqlitePool, config: Arc<Config>, clients: Clients, blobs: Blobs) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "authors" / i64)
         .and(warp::delete())
-        .and(with_auth())
-        .and_then(move |id: i64, _: String| {
+        .and(with_role(db.clone(), config, Role::Editor))
+        .and_then(move |id: i64, username: String| {
             let db_clo = db.clone(); // Clone the db object
             let clients_clone = clients.clone();
+            let blobs = blobs.clone();
             async move {
-                delete_author(&db_clo, id, clients_clone).await // Call the function with the cloned db
+                delete_author(&db_clo, &blobs, id, username, clients_clone).await // Call the function with the cloned db
             }
         })
 }
 
-// pub fn upload_route() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-
+//ROUTE TO SET AN AUTHOR PHOTO WITH multipart/form-data
+pub fn upload_author_photo_route(db: SqlitePool, config: Arc<Config>, clients: Clients, blobs: Blobs) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // The photo plus the multipart framing
+    let max_request = MAX_IMAGE_SIZE as u64 + 64 * 1024;
+
+    warp::path!("api" / "authors" / i64 / "photo")
+        .and(warp::put())
+        .and(with_role(db.clone(), config.clone(), Role::Editor))
+        .and(warp::multipart::form().max_length(max_request))
+        .and_then(move |id: i64, username: String, form: FormData| {
+            let db_clo = db.clone();
+            let clients_clone = clients.clone();
+            let blobs = blobs.clone();
+            let config = config.clone();
+            async move {
+                upload_author_photo(&db_clo, &blobs, &config.photos, id, username, form, clients_clone).await
+            }
+        })
+}
 
-//     warp::path("upload")
-//         .and(warp::post())
-//         .and(warp::multipart::form().max_length(5_000_000))
-//         .and_then(upload)
-// }
+//ROUTE TO GET AN AUTHOR PHOTO AS AN IMAGE. PUBLIC LIKE THE AUTHOR LIST IT IS LINKED FROM
+pub fn get_author_photo_route(db: SqlitePool, blobs: Blobs) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "authors" / i64 / "photo")
+        .and(warp::get().or(warp::head()).unify())
+        .and(warp::method())
+        .and(warp::header::headers_cloned())
+        .and(warp::query::<PhotoQueryParam>())
+        .and_then(move |id: i64, method: Method, headers: HeaderMap, query: PhotoQueryParam| {
+            let db_clo = db.clone();
+            let blobs = blobs.clone();
+            async move {
+                get_author_photo(&db_clo, &blobs, id, method, headers, query).await
+            }
+        })
+}
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+use warp::Filter;
+use sqlx::SqlitePool;
+use crate::config::Config;
+use crate::handlers::events_handler;
+use crate::models::response::EventsQueryParam;
+use crate::ws::clients::Clients;
+
+
+//ROUTE TO STREAM CHANGE EVENTS AS SERVER-SENT EVENTS
+pub fn get_events_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+    clients: Clients,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "events")
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(warp::query::<EventsQueryParam>())
+        .and_then(move |headers, query| {
+            let db = db.clone();
+            let config = config.clone();
+            let clients = clients.clone();
+            async move {
+                events_handler::get_events(db, config, clients, headers, query).await
+            }
+        })
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use warp::http::{HeaderMap, Method};
+use warp::Filter;
+use sqlx::SqlitePool;
+use crate::config::Config;
+use crate::handlers::files_handler;
+use crate::Middleware::auth::with_auth;
+use crate::models::response::DownloadQueryParam;
+use crate::storage::Blobs;
+
+
+//ROUTE TO DOWNLOAD ALL FILES OF A POST AS A ZIP ARCHIVE
+pub fn get_post_files_zip_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+    blobs: Blobs,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "posts" / i64 / "files.zip")
+        .and(warp::get())
+        .and(with_auth(db.clone(), config))
+        .and_then(move |id: i64, _: String| {
+            let db_clone = db.clone();
+            let blobs = blobs.clone();
+            async move {
+                files_handler::get_post_files_zip(&db_clone, &blobs, id).await
+            }
+        })
+}
+
+
+//ROUTE TO DOWNLOAD A SINGLE FILE
+pub fn get_file_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+    blobs: Blobs,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "files" / i64)
+        .and(warp::get().or(warp::head()).unify())
+        .and(with_auth(db.clone(), config))
+        .and(warp::method())
+        .and(warp::header::headers_cloned())
+        .and(warp::query::<DownloadQueryParam>())
+        .and_then(move |id: i64, _: String, method: Method, headers: HeaderMap, query: DownloadQueryParam| {
+            let db_clone = db.clone();
+            let blobs = blobs.clone();
+            async move {
+                files_handler::get_file(&db_clone, &blobs, id, method, headers, query).await
+            }
+        })
+}
@@ -1,3 +1,6 @@
 pub mod author_routes;
+pub mod events_routes;
+pub mod files_routes;
 pub mod posts_routes;
+pub mod uploads_routes;
 pub mod user_routes;
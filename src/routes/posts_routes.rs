@@ -1,8 +1,14 @@
+use std::sync::Arc;
+use warp::multipart::FormData;
 use warp::{Filter, Rejection, Reply};
 use sqlx::SqlitePool;
-use crate::Middleware::auth::with_auth;
-use crate::handlers::posts_handler::{self, create_post, update_post};
+use crate::config::Config;
+use crate::Middleware::auth::{with_auth, with_role};
+use crate::models::user::Role;
+use crate::handlers::posts_handler::{self, create_post, update_post, upload_post_files};
+use crate::Middleware::mime_check::MAX_FILE_SIZE;
 use crate::ws::clients::Clients;
+use crate::storage::Blobs;
 use crate::models::response::{ CreatePostRequest, UpdatePostRequest, PageQueryParam, SearchQueryParam};
 
 
@@ -24,7 +30,7 @@ pub fn get_posts(db: SqlitePool) -> impl Filter<Extract = impl warp::Reply, Erro
         .and_then(move |page_query_param:PageQueryParam, search_query:SearchQueryParam| {
             let db_clone = db.clone();
             async move {
-                let search_param = search_query.search;
+                let search_param = search_query.term();
                 posts_handler::get_all_posts(page_query_param, search_param, &db_clone).await 
             }
         })
@@ -35,10 +41,11 @@ pub fn get_posts(db: SqlitePool) -> impl Filter<Extract = impl warp::Reply, Erro
 //ROUTE FOR SINGLE POST
 pub fn get_post_route(
     db: SqlitePool,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "posts" / i64)
         .and(warp::get())
-        .and(with_auth()) // Add authentication here
+        .and(with_auth(db.clone(), config)) // Add authentication here
         .and(warp::any().map(move || db.clone())) // Inject the database pool
         .and_then(|id: i64, _ : String, db: SqlitePool| {
             let db_clone = db.clone();
@@ -52,19 +59,23 @@ pub fn get_post_route(
 //ROUTE TO CREATE A POST
 pub fn create_post_route(
     pool: SqlitePool,
-    clients: Clients
+    config: Arc<Config>,
+    clients: Clients,
+    blobs: Blobs,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::post()
-        .and(warp::path!("api" / "posts"))
-        .and(with_auth())
+    warp::path!("api" / "posts")
+        .and(warp::post())
+        .and(with_role(pool.clone(), config.clone(), Role::Editor))
         .and(warp::body::json())
         .and(warp::any().map(move || pool.clone()))
-        .and_then(move| _: String, data: CreatePostRequest, db: SqlitePool| {
+        .and_then(move| username: String, data: CreatePostRequest, db: SqlitePool| {
             let clients_clone = clients.clone();
+            let blobs = blobs.clone();
+            let config = config.clone();
 
             async move {
                 
-                create_post(&db, data, clients_clone).await
+                create_post(&db, &blobs, &config, data, username, clients_clone).await
             }
         })
             
@@ -84,9 +95,9 @@ pub fn create_post_route(
 
 //ROUTE FOR POSTS BY AUTHOR ID
 
-pub fn get_posts_by_author(db: SqlitePool) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone{
+pub fn get_posts_by_author(db: SqlitePool, config: Arc<Config>) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone{
     warp::path!("api" / "posts" / "author" / i64)
-        .and(with_auth())
+        .and(with_auth(db.clone(), config))
         .and(warp::get())
         .and_then(move |id: i64, _: String,| {
             let db_clone = db.clone();
@@ -100,18 +111,22 @@ pub fn get_posts_by_author(db: SqlitePool) -> impl Filter<Extract = impl warp::R
 //ROUTE TO UPDATE A POST
 pub fn update_post_route(
     pool: SqlitePool,
-    clients: Clients
+    config: Arc<Config>,
+    clients: Clients,
+    blobs: Blobs,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::patch()
-        .and(warp::path!("api" / "posts" / i64))
-        .and(with_auth())
+    warp::path!("api" / "posts" / i64)
+        .and(warp::patch())
+        .and(with_role(pool.clone(), config.clone(), Role::Editor))
         .and(warp::body::json())
         .and(warp::any().map(move || pool.clone()))
-        .and_then( move|id: i64,_: String, data: UpdatePostRequest, db: SqlitePool|{
+        .and_then( move|id: i64, username: String, data: UpdatePostRequest, db: SqlitePool|{
             let clients_clone = clients.clone();
+            let blobs = blobs.clone();
+            let config = config.clone();
             async move {
                 
-                update_post(&db, data, id,clients_clone).await
+                update_post(&db, &blobs, &config, data, id, username, clients_clone).await
             }
         })
             
@@ -119,17 +134,63 @@ pub fn update_post_route(
 
 
 
+//ROUTE TO ATTACH FILES TO A POST WITH multipart/form-data
+pub fn upload_post_files_route(
+    pool: SqlitePool,
+    config: Arc<Config>,
+    clients: Clients,
+    blobs: Blobs,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    // Room for a handful of maximum size files plus the multipart framing
+    let max_request = (MAX_FILE_SIZE as u64) * 5 + 64 * 1024;
+
+    warp::path!("api" / "posts" / i64 / "files")
+        .and(warp::post())
+        .and(with_role(pool.clone(), config.clone(), Role::Editor))
+        .and(warp::multipart::form().max_length(max_request))
+        .and(warp::any().map(move || pool.clone()))
+        .and_then(move |id: i64, username: String, form: FormData, db: SqlitePool| {
+            let clients_clone = clients.clone();
+            let blobs = blobs.clone();
+            let config = config.clone();
+            async move {
+                upload_post_files(&db, &blobs, &config, id, username, form, clients_clone).await
+            }
+        })
+}
+
+
+
+//ROUTE TO LIST THE FILES OF A POST
+pub fn get_post_files_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "posts" / i64 / "files")
+        .and(warp::get())
+        .and(with_auth(db.clone(), config))
+        .and_then(move |id: i64, _: String| {
+            let db_clone = db.clone();
+            async move {
+                posts_handler::get_post_files(&db_clone, id).await
+            }
+        })
+}
+
+
+
 //DELETE POST ROUTE
-pub fn delete_post_route(db: SqlitePool, clients: Clients) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn delete_post_route(db: SqlitePool, config: Arc<Config>, clients: Clients, blobs: Blobs) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     
     warp::path!("api" / "posts" / i64)
         .and(warp::delete())
-        .and(with_auth())
-        .and_then(move |id: i64, _: String|  {
+        .and(with_role(db.clone(), config, Role::Editor))
+        .and_then(move |id: i64, username: String|  {
             let db_clo = db.clone(); // Clone the db object
             let clients_clone = clients.clone();
+            let blobs = blobs.clone();
             async move {
-                posts_handler::delete_post(&db_clo, id, clients_clone).await // Call the function with the cloned db
+                posts_handler::delete_post(&db_clo, &blobs, id, username, clients_clone).await // Call the function with the cloned db
             }
         })
 }
@@ -138,15 +199,18 @@ pub fn delete_post_route(db: SqlitePool, clients: Clients) -> impl Filter<Extrac
 // ROUTE TO DOWNLOAD FILE
 pub fn download_file(
     db: SqlitePool,
+    config: Arc<Config>,
+    blobs: Blobs,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "posts" / i64 / "download")
         .and(warp::get())
-        .and(with_auth()) // Add authentication here
+        .and(with_auth(db.clone(), config)) // Add authentication here
         .and(warp::any().map(move || db.clone())) // Inject the database pool
-        .and_then(|id: i64, _ : String, db: SqlitePool| {
+        .and_then(move |id: i64, _ : String, db: SqlitePool| {
             let db_clone = db.clone();
+            let blobs = blobs.clone();
             async move {
-                posts_handler::download_files_by_id(&db_clone, id).await // Call the get_post handler
+                posts_handler::download_files_by_id(&db_clone, &blobs, id).await // Call the get_post handler
             }
         })
 }
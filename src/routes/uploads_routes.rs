@@ -0,0 +1,91 @@
+use std::sync::Arc;
+use warp::http::HeaderMap;
+use warp::Filter;
+use sqlx::SqlitePool;
+use crate::config::Config;
+use crate::models::user::Role;
+use crate::handlers::uploads_handler;
+use crate::Middleware::auth::with_role;
+
+
+//ROUTE FOR THE tus CAPABILITIES
+pub fn tus_options_route() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "uploads")
+        .and(warp::options())
+        .and_then(uploads_handler::tus_options)
+}
+
+
+//ROUTE TO START A RESUMABLE UPLOAD
+pub fn create_upload_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "uploads")
+        .and(warp::post())
+        .and(with_role(db.clone(), config.clone(), Role::Editor))
+        .and(warp::header::headers_cloned())
+        .and_then(move |username: String, headers: HeaderMap| {
+            let db_clone = db.clone();
+            let config = config.clone();
+            async move {
+                uploads_handler::create_tus_upload(&db_clone, &config.uploads, &config.quotas, username, headers).await
+            }
+        })
+}
+
+
+//ROUTE FOR THE OFFSET OF A RESUMABLE UPLOAD
+pub fn head_upload_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "uploads" / String)
+        .and(warp::head())
+        .and(with_role(db.clone(), config, Role::Editor))
+        .and(warp::header::headers_cloned())
+        .and_then(move |id: String, username: String, headers: HeaderMap| {
+            let db_clone = db.clone();
+            async move {
+                uploads_handler::head_tus_upload(&db_clone, id, username, headers).await
+            }
+        })
+}
+
+
+//ROUTE TO SEND THE NEXT BYTES OF A RESUMABLE UPLOAD
+pub fn patch_upload_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "uploads" / String)
+        .and(warp::patch())
+        .and(with_role(db.clone(), config.clone(), Role::Editor))
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and_then(move |id: String, username: String, headers: HeaderMap, body| {
+            let db_clone = db.clone();
+            let config = config.clone();
+            async move {
+                uploads_handler::patch_tus_upload(&db_clone, &config.uploads, id, username, headers, body).await
+            }
+        })
+}
+
+
+//ROUTE TO CANCEL A RESUMABLE UPLOAD
+pub fn delete_upload_route(
+    db: SqlitePool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "uploads" / String)
+        .and(warp::delete())
+        .and(with_role(db.clone(), config, Role::Editor))
+        .and(warp::header::headers_cloned())
+        .and_then(move |id: String, username: String, headers: HeaderMap| {
+            let db_clone = db.clone();
+            async move {
+                uploads_handler::delete_tus_upload(&db_clone, id, username, headers).await
+            }
+        })
+}
@@ -1,14 +1,18 @@
+use std::sync::Arc;
 use warp::{Filter, Rejection, Reply};
 use sqlx::SqlitePool;
+use crate::config::Config;
 use crate::handlers::user_handlers;
-use crate::models::response::UserRequest;
+use crate::Middleware::auth::{with_auth, with_claims, with_role, Claims};
+use crate::models::response::{UserRequest, RefreshTokenRequest, UpdateRoleRequest, UpdateQuotaRequest};
+use crate::models::user::Role;
 
 
 pub fn register_user_route(
     pool: SqlitePool,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::post()
-        .and(warp::path!("api" / "register"))
+    warp::path!("api" / "register")
+        .and(warp::post())
         .and(warp::body::json())
         .and(warp::any().map(move || pool.clone()))
         .and_then(|data: UserRequest, db: SqlitePool| async move {
@@ -24,14 +28,16 @@ pub fn register_user_route(
     }
 
 pub fn login_user_route(
-    pool: SqlitePool
+    pool: SqlitePool,
+    config: Arc<Config>
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::post()
-    .and(warp::path!("api" / "login"))
+    warp::path!("api" / "login")
+    .and(warp::post())
     .and(warp::body::json())
     .and(warp::any().map(move || pool.clone()))
-    .and_then(|data: UserRequest, db: SqlitePool| async move{
-        let result = user_handlers::login_user_handler(&db, data).await;
+    .and(warp::any().map(move || config.clone()))
+    .and_then(|data: UserRequest, db: SqlitePool, config: Arc<Config>| async move{
+        let result = user_handlers::login_user_handler(&db, &config, data).await;
 
         match  result {
             Ok(reply) => Ok(reply),
@@ -42,4 +48,74 @@ pub fn login_user_route(
     })
 }
 
+pub fn refresh_token_route(
+    pool: SqlitePool,
+    config: Arc<Config>
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "token" / "refresh")
+    .and(warp::post())
+    .and(warp::body::json())
+    .and(warp::any().map(move || pool.clone()))
+    .and(warp::any().map(move || config.clone()))
+    .and_then(|data: RefreshTokenRequest, db: SqlitePool, config: Arc<Config>| async move {
+        user_handlers::refresh_token_handler(&db, &config, data).await
+    })
+}
+
+pub fn logout_route(
+    pool: SqlitePool,
+    config: Arc<Config>
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let db = pool.clone();
+    warp::path!("api" / "logout")
+    .and(warp::post())
+    .and(with_claims(db, config))
+    .and(warp::any().map(move || pool.clone()))
+    .and_then(|claims: Claims, db: SqlitePool| async move {
+        user_handlers::logout_handler(&db, claims).await
+    })
+}
+
+pub fn update_role_route(
+    pool: SqlitePool,
+    config: Arc<Config>
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "users" / String / "role")
+    .and(warp::patch())
+    .and(with_role(pool.clone(), config, Role::Admin))
+    .and(warp::body::json())
+    .and(warp::any().map(move || pool.clone()))
+    .and_then(|username: String, _: String, data: UpdateRoleRequest, db: SqlitePool| async move {
+        user_handlers::update_role_handler(&db, username, data).await
+    })
+}
+
+pub fn update_quota_route(
+    pool: SqlitePool,
+    config: Arc<Config>
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "users" / String / "quota")
+    .and(warp::patch())
+    .and(with_role(pool.clone(), config, Role::Admin))
+    .and(warp::body::json())
+    .and(warp::any().map(move || pool.clone()))
+    .and_then(|username: String, _: String, data: UpdateQuotaRequest, db: SqlitePool| async move {
+        user_handlers::update_quota_handler(&db, username, data).await
+    })
+}
 
+pub fn usage_route(
+    pool: SqlitePool,
+    config: Arc<Config>
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "me" / "usage")
+    .and(warp::get())
+    .and(with_auth(pool.clone(), config.clone()))
+    .and(warp::any().map(move || pool.clone()))
+    .and_then(move |username: String, db: SqlitePool| {
+        let config = config.clone();
+        async move {
+            user_handlers::usage_handler(&db, &config.quotas, username).await
+        }
+    })
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Scanner, Verdict};
+use crate::errors::ApiError;
+
+// clamd's StreamMaxLength defaults to 25 MB, chunks are well below that
+const CHUNK_SIZE: usize = 64 * 1024;
+// clamd answers in one short line
+const MAX_REPLY: usize = 4096;
+
+//A CLAMAV DAEMON, SPOKEN TO OVER ITS INSTREAM PROTOCOL
+//
+// A new connection per scan: the upload is sent as length-prefixed chunks
+// ended by an empty one, clamd answers "stream: OK" or
+// "stream: <signature> FOUND" and closes the connection
+pub struct ClamdScanner {
+    // host:port, or unix:/path/to/socket
+    address: String,
+    timeout: Duration,
+}
+
+impl ClamdScanner {
+    pub fn new(address: String, timeout: Duration) -> ClamdScanner {
+        ClamdScanner { address, timeout }
+    }
+
+    async fn connect_and_scan(&self, data: &[u8]) -> Result<Verdict, ApiError> {
+        #[cfg(unix)]
+        if let Some(path) = self.address.strip_prefix("unix:") {
+            let stream = tokio::net::UnixStream::connect(path).await.map_err(|e| connect_error(&self.address, e))?;
+            return instream(stream, data).await;
+        }
+
+        let stream = TcpStream::connect(&self.address).await.map_err(|e| connect_error(&self.address, e))?;
+        instream(stream, data).await
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamdScanner {
+    fn name(&self) -> &'static str {
+        "clamd"
+    }
+
+    async fn scan(&self, data: &[u8]) -> Result<Verdict, ApiError> {
+        tokio::time::timeout(self.timeout, self.connect_and_scan(data))
+            .await
+            .map_err(|_| ApiError::Internal(format!("clamd at {} did not answer in time", self.address)))?
+    }
+}
+
+fn connect_error(address: &str, e: std::io::Error) -> ApiError {
+    ApiError::Internal(format!("Could not connect to clamd at {}: {}", address, e))
+}
+
+fn io_error(e: std::io::Error) -> ApiError {
+    ApiError::Internal(format!("clamd connection failed: {}", e))
+}
+
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, data: &[u8]) -> Result<Verdict, ApiError> {
+    // The z prefix makes clamd end its reply with a NUL instead of a newline
+    stream.write_all(b"zINSTREAM\0").await.map_err(io_error)?;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes()).await.map_err(io_error)?;
+        stream.write_all(chunk).await.map_err(io_error)?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await.map_err(io_error)?;
+    stream.flush().await.map_err(io_error)?;
+
+    // Read up to the NUL, or until clamd hangs up
+    let mut reply = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !reply.contains(&0) && reply.len() < MAX_REPLY {
+        let read = stream.read(&mut buffer).await.map_err(io_error)?;
+        if read == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buffer[..read]);
+    }
+    parse_reply(&String::from_utf8_lossy(&reply))
+}
+
+// "stream: OK", "stream: Eicar-Test-Signature FOUND", or "... ERROR" when
+// clamd couldn't scan, e.g. because the upload is over StreamMaxLength
+fn parse_reply(reply: &str) -> Result<Verdict, ApiError> {
+    let reply = reply.trim_end_matches(['\0', '\n', '\r']);
+    let status = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if status == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = status.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.to_string()))
+    } else {
+        Err(ApiError::Internal(format!("clamd could not scan the upload: {:?}", reply)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_reply() {
+        assert_eq!(parse_reply("stream: OK\0").unwrap(), Verdict::Clean);
+        assert_eq!(parse_reply("stream: OK\n").unwrap(), Verdict::Clean);
+    }
+
+    #[test]
+    fn found_reply_names_the_signature() {
+        assert_eq!(
+            parse_reply("stream: Eicar-Test-Signature FOUND\0").unwrap(),
+            Verdict::Infected("Eicar-Test-Signature".to_string())
+        );
+    }
+
+    #[test]
+    fn error_reply_is_an_error() {
+        assert!(parse_reply("INSTREAM size limit exceeded. ERROR\0").is_err());
+        assert!(parse_reply("stream: Can't allocate memory ERROR\0").is_err());
+        assert!(parse_reply("").is_err());
+    }
+}
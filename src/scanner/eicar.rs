@@ -0,0 +1,50 @@
+use async_trait::async_trait;
+
+use super::{Scanner, Verdict};
+use crate::errors::ApiError;
+
+// The standard antivirus test file. Harmless, but every scanner flags it
+const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+const SIGNATURE: &str = "Eicar-Test-Signature";
+
+//FLAGS THE EICAR TEST FILE AND NOTHING ELSE
+//
+// Lets quarantine be tried out without running ClamAV. Unlike a real scanner
+// it finds the string anywhere in the upload, not only at the start
+pub struct EicarScanner;
+
+#[async_trait]
+impl Scanner for EicarScanner {
+    fn name(&self) -> &'static str {
+        "eicar"
+    }
+
+    async fn scan(&self, data: &[u8]) -> Result<Verdict, ApiError> {
+        if data.windows(EICAR.len()).any(|window| window == EICAR) {
+            Ok(Verdict::Infected(SIGNATURE.to_string()))
+        } else {
+            Ok(Verdict::Clean)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_eicar_inside_a_larger_upload() {
+        let mut data = b"%PDF-1.4 some text before ".to_vec();
+        data.extend_from_slice(EICAR);
+        data.extend_from_slice(b" and after");
+
+        assert_eq!(EicarScanner.scan(&data).await.unwrap(), Verdict::Infected(SIGNATURE.to_string()));
+    }
+
+    #[tokio::test]
+    async fn clean_data_passes() {
+        assert_eq!(EicarScanner.scan(b"just a text file").await.unwrap(), Verdict::Clean);
+        assert_eq!(EicarScanner.scan(&EICAR[..EICAR.len() - 1]).await.unwrap(), Verdict::Clean);
+        assert_eq!(EicarScanner.scan(b"").await.unwrap(), Verdict::Clean);
+    }
+}
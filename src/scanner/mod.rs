@@ -0,0 +1,100 @@
+pub mod clamd;
+pub mod eicar;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::config::{ScanBackend, ScanConfig};
+use crate::errors::ApiError;
+use clamd::ClamdScanner;
+use eicar::EicarScanner;
+
+// What a scanner made of an upload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    // Names the signature that matched
+    Infected(String),
+}
+
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Fails when the scanner can't give an answer, the upload is refused then
+    async fn scan(&self, data: &[u8]) -> Result<Verdict, ApiError>;
+}
+
+// Recorded in files.scan_status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStatus {
+    // Stored while no scanner was configured
+    Unscanned,
+    Clean,
+    // Kept for admins to look at, never downloaded
+    Quarantined,
+}
+
+impl ScanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanStatus::Unscanned => "unscanned",
+            ScanStatus::Clean => "clean",
+            ScanStatus::Quarantined => "quarantined",
+        }
+    }
+}
+
+// A scan's status and, for quarantined files, the signature that matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanReport {
+    pub status: ScanStatus,
+    pub result: Option<String>,
+}
+
+impl ScanReport {
+    pub fn unscanned() -> ScanReport {
+        ScanReport { status: ScanStatus::Unscanned, result: None }
+    }
+}
+
+//THE CONFIGURED SCANNER, IF ANY, THAT EVERY UPLOAD GOES THROUGH
+#[derive(Clone)]
+pub struct UploadScanner {
+    scanner: Option<Arc<dyn Scanner>>,
+}
+
+impl UploadScanner {
+    pub fn new(config: &ScanConfig) -> UploadScanner {
+        let timeout = Duration::from_secs(config.timeout_seconds);
+        let scanner: Option<Arc<dyn Scanner>> = match config.backend {
+            ScanBackend::None => None,
+            ScanBackend::Eicar => Some(Arc::new(EicarScanner)),
+            ScanBackend::Clamd => Some(Arc::new(ClamdScanner::new(config.clamd_address.clone(), timeout))),
+        };
+        UploadScanner { scanner }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.scanner.is_some()
+    }
+
+    pub async fn scan(&self, data: &[u8]) -> Result<ScanReport, ApiError> {
+        let scanner = match &self.scanner {
+            Some(scanner) => scanner,
+            None => return Ok(ScanReport::unscanned()),
+        };
+
+        match scanner.scan(data).await {
+            Ok(Verdict::Clean) => Ok(ScanReport { status: ScanStatus::Clean, result: None }),
+            Ok(Verdict::Infected(signature)) => Ok(ScanReport { status: ScanStatus::Quarantined, result: Some(signature) }),
+            Err(e) => {
+                // The details are for the logs, clients only learn to retry
+                eprintln!("Malware scan with {} failed: {:?}", scanner.name(), e);
+                Err(ApiError::ServiceUnavailable("Uploads can't be scanned right now, try again later".to_string()))
+            }
+        }
+    }
+}
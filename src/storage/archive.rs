@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use bytes::Bytes;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use warp::hyper::body::Sender;
+use warp::hyper::Body;
+
+use super::Blobs;
+use crate::db::files::FileReader;
+use crate::errors::ApiError;
+use crate::models::files::FileMetadata;
+
+// How much of a file is read from its store at a time
+const CHUNK_SIZE: u64 = 256 * 1024;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+// Sizes and CRC follow the data, names are UTF-8
+const FLAGS: u16 = 0x0008 | 0x0800;
+// 2.0, the first version with data descriptors
+const VERSION: u16 = 20;
+// 1980-01-01 00:00, the earliest time a ZIP can hold
+const DOS_EPOCH: (u16, u16) = (0, 0x21);
+
+// What the central directory needs to know about an entry once it is written
+struct Entry {
+    name: String,
+    crc32: u32,
+    size: u32,
+    offset: u32,
+    time: (u16, u16),
+}
+
+//A ZIP ARCHIVE OF FILES, WRITTEN WHILE IT IS SENT
+//
+// Entries are stored uncompressed: attachments are mostly compressed formats
+// already. Each file is read in chunks, so only one chunk is in memory at a
+// time. The archive has no ZIP64 records, too large a set of files is refused
+// before anything is sent
+pub fn zip_stream(blobs: Blobs, files: Vec<FileMetadata>) -> Result<Body, ApiError> {
+    let names = entry_names(&files);
+
+    // Local header, data, data descriptor and central directory record per
+    // file, then the end of central directory record
+    let total: u64 = files
+        .iter()
+        .zip(&names)
+        .map(|(file, name)| 30 + file.size.max(0) as u64 + 16 + 46 + 2 * name.len() as u64)
+        .sum::<u64>()
+        + 22;
+    if total > u32::MAX as u64 || files.len() > u16::MAX as usize {
+        return Err(ApiError::PayloadTooLarge("The files are too large to download as one ZIP archive".to_string()));
+    }
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(&mut sender, &blobs, &files, names).await {
+            // The status line is gone already, cutting the body short is the
+            // only way left to tell the client the archive is incomplete
+            eprintln!("ZIP download failed: {:?}", e);
+            sender.abort();
+        }
+    });
+
+    Ok(body)
+}
+
+// The stored filenames, made unique. Later duplicates get " (2)", " (3)", ...
+// before the extension. Case is ignored since most filesystems ignore it
+fn entry_names(files: &[FileMetadata]) -> Vec<String> {
+    let mut taken = HashSet::new();
+
+    files
+        .iter()
+        .map(|file| {
+            let name = file.filename.clone().unwrap_or_else(|| format!("file-{}", file.id));
+            // Never let an entry point outside the folder it is extracted to
+            let name = name.replace(['/', '\\'], "_");
+            let (stem, extension) = match name.rfind('.') {
+                Some(dot) if dot > 0 => name.split_at(dot),
+                _ => (name.as_str(), ""),
+            };
+
+            let mut candidate = name.clone();
+            let mut copy = 2;
+            while !taken.insert(candidate.to_lowercase()) {
+                candidate = format!("{} ({}){}", stem, copy, extension);
+                copy += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
+async fn write_archive(sender: &mut Sender, blobs: &Blobs, files: &[FileMetadata], names: Vec<String>) -> Result<(), ApiError> {
+    let mut offset: u32 = 0;
+    let mut entries = Vec::new();
+
+    for (file, name) in files.iter().zip(names) {
+        let time = file.uploaded_at.as_deref().map(dos_time).unwrap_or(DOS_EPOCH);
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        put_u32(&mut header, LOCAL_HEADER);
+        put_u16(&mut header, VERSION);
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, 0); // stored
+        put_u16(&mut header, time.0);
+        put_u16(&mut header, time.1);
+        put_u32(&mut header, 0); // CRC and sizes are in the data descriptor
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, 0);
+        header.extend_from_slice(name.as_bytes());
+        let header_len = header.len() as u32;
+        send(sender, header).await?;
+
+        let size = file.size.max(0) as u64;
+        let mut crc = crc32fast::Hasher::new();
+        let mut written: u64 = 0;
+        let mut reader = FileReader::new(blobs, file, 0, size)?;
+        while written < size {
+            let chunk = reader.next_chunk(CHUNK_SIZE).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            crc.update(&chunk);
+            written += chunk.len() as u64;
+            send(sender, chunk).await?;
+        }
+        if written != size {
+            return Err(ApiError::Internal(format!("File {} is {} bytes, expected {}", file.id, written, size)));
+        }
+
+        let entry = Entry { name, crc32: crc.finalize(), size: size as u32, offset, time };
+
+        let mut descriptor = Vec::with_capacity(16);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR);
+        put_u32(&mut descriptor, entry.crc32);
+        put_u32(&mut descriptor, entry.size);
+        put_u32(&mut descriptor, entry.size);
+        send(sender, descriptor).await?;
+
+        offset += header_len + entry.size + 16;
+        entries.push(entry);
+    }
+
+    let mut directory = Vec::new();
+    for entry in &entries {
+        put_u32(&mut directory, CENTRAL_HEADER);
+        put_u16(&mut directory, VERSION);
+        put_u16(&mut directory, VERSION);
+        put_u16(&mut directory, FLAGS);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, entry.time.0);
+        put_u16(&mut directory, entry.time.1);
+        put_u32(&mut directory, entry.crc32);
+        put_u32(&mut directory, entry.size);
+        put_u32(&mut directory, entry.size);
+        put_u16(&mut directory, entry.name.len() as u16);
+        put_u16(&mut directory, 0); // extra field
+        put_u16(&mut directory, 0); // comment
+        put_u16(&mut directory, 0); // disk
+        put_u16(&mut directory, 0); // internal attributes
+        put_u32(&mut directory, 0); // external attributes
+        put_u32(&mut directory, entry.offset);
+        directory.extend_from_slice(entry.name.as_bytes());
+    }
+
+    let directory_len = directory.len() as u32;
+    put_u32(&mut directory, END_OF_CENTRAL_DIRECTORY);
+    put_u16(&mut directory, 0);
+    put_u16(&mut directory, 0);
+    put_u16(&mut directory, entries.len() as u16);
+    put_u16(&mut directory, entries.len() as u16);
+    put_u32(&mut directory, directory_len);
+    put_u32(&mut directory, offset);
+    put_u16(&mut directory, 0);
+    send(sender, directory).await
+}
+
+// Waits while the client is behind, so a slow download never piles up chunks
+async fn send(sender: &mut Sender, data: impl Into<Bytes>) -> Result<(), ApiError> {
+    sender
+        .send_data(data.into())
+        .await
+        .map_err(|e| ApiError::Internal(format!("Client went away: {}", e)))
+}
+
+// uploaded_at ("2026-10-18 13:36:59") as the DOS (time, date) pair
+fn dos_time(uploaded_at: &str) -> (u16, u16) {
+    match NaiveDateTime::parse_from_str(uploaded_at, "%Y-%m-%d %H:%M:%S") {
+        Ok(at) if (1980..2108).contains(&at.year()) => (
+            ((at.hour() << 11) | (at.minute() << 5) | (at.second() / 2)) as u16,
+            (((at.year() as u32 - 1980) << 9) | (at.month() << 5) | at.day()) as u16,
+        ),
+        _ => DOS_EPOCH,
+    }
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+    use crate::config::{ScanConfig, StorageConfig};
+    use crate::db::files::{insert_file_rows, store_files, NewFile};
+    use crate::db::memory_db;
+
+    async fn archive(files: Vec<(&str, Vec<u8>)>) -> zip::ZipArchive<Cursor<Vec<u8>>> {
+        let db = memory_db().await;
+        sqlx::query("INSERT INTO users (id, username, password) VALUES (1, 'ann', 'x')").execute(&db).await.unwrap();
+        sqlx::query("INSERT INTO authors (id, name, surname) VALUES (1, 'Ann', 'Lee')").execute(&db).await.unwrap();
+        sqlx::query("INSERT INTO posts (id, title, content, author_id) VALUES (1, 'Hello', 'World', 1)").execute(&db).await.unwrap();
+
+        let blobs = Blobs::new(db.clone(), &StorageConfig::default(), &ScanConfig::default());
+        let files: Vec<NewFile> = files.into_iter().map(|(name, data)| NewFile::new(Some(name), data)).collect();
+        let stored = insert_file_rows(&mut db.acquire().await.unwrap(), &blobs, 1, 1, &files).await.unwrap();
+        store_files(&db, &blobs, &stored, &files).await.unwrap();
+
+        let body = zip_stream(blobs, stored).unwrap();
+        let bytes = warp::hyper::body::to_bytes(body).await.unwrap();
+        zip::ZipArchive::new(Cursor::new(bytes.to_vec())).unwrap()
+    }
+
+    fn contents(archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, index: usize) -> (String, Vec<u8>) {
+        let mut entry = archive.by_index(index).unwrap();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        (entry.name().to_string(), data)
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_a_zip_reader() {
+        // Larger than CHUNK_SIZE, so it is read and checksummed in pieces
+        let large: Vec<u8> = (0..CHUNK_SIZE as usize * 2 + 123).map(|i| (i % 251) as u8).collect();
+        let mut archive = archive(vec![("notes.txt", b"hello".to_vec()), ("empty.txt", Vec::new()), ("large.bin", large.clone())]).await;
+
+        assert_eq!(archive.len(), 3);
+        assert_eq!(contents(&mut archive, 0), ("notes.txt".to_string(), b"hello".to_vec()));
+        assert_eq!(contents(&mut archive, 1), ("empty.txt".to_string(), Vec::new()));
+        assert_eq!(contents(&mut archive, 2), ("large.bin".to_string(), large));
+    }
+
+    #[tokio::test]
+    async fn duplicate_names_get_a_suffix() {
+        let mut archive = archive(vec![
+            ("report.pdf.txt", b"1".to_vec()),
+            ("REPORT.pdf.txt", b"2".to_vec()),
+            ("report.pdf.txt", b"3".to_vec()),
+            ("README", b"4".to_vec()),
+            ("README", b"5".to_vec()),
+        ])
+        .await;
+
+        let names: Vec<String> = (0..archive.len()).map(|i| contents(&mut archive, i).0).collect();
+        assert_eq!(names, ["report.pdf.txt", "REPORT.pdf (2).txt", "report.pdf (3).txt", "README", "README (2)"]);
+        assert_eq!(contents(&mut archive, 1).1, b"2");
+    }
+
+    #[test]
+    fn dos_time_packs_the_upload_time() {
+        // 2026-10-18 13:36:58: hour 13, minute 36, 29 two-second steps; 46 years after 1980
+        assert_eq!(dos_time("2026-10-18 13:36:58"), ((13 << 11) | (36 << 5) | 29, (46 << 9) | (10 << 5) | 18));
+        assert_eq!(dos_time("1970-01-01 00:00:00"), DOS_EPOCH);
+        assert_eq!(dos_time("not a time"), DOS_EPOCH);
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{BlobOwner, BlobStore};
+use crate::errors::ApiError;
+
+//CONTENT ADDRESSED BLOBS ON DISK: <root>/ab/cd/abcd...
+//
+// Identical uploads share one file. A file is removed once no row that is
+// stored on the filesystem references its hash any more
+pub struct FsBlobStore {
+    db: SqlitePool,
+    root: PathBuf,
+    // Serialises writes and removals, so a blob can't be deleted while a new
+    // row is being pointed at it
+    lock: Mutex<()>,
+}
+
+impl FsBlobStore {
+    pub fn new(db: SqlitePool, root: PathBuf) -> FsBlobStore {
+        FsBlobStore { db, root, lock: Mutex::new(()) }
+    }
+
+    fn path(&self, sha256: &str) -> Result<PathBuf, ApiError> {
+        // The hash becomes a path, so it must be exactly what we generate
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            return Err(ApiError::Internal(format!("Invalid blob hash {:?}", sha256)));
+        }
+        Ok(self.root.join(&sha256[0..2]).join(&sha256[2..4]).join(sha256))
+    }
+
+    async fn write(&self, path: &PathBuf, data: &[u8]) -> Result<(), ApiError> {
+        let io_error = |e: std::io::Error| ApiError::Internal(format!("Could not write blob {}: {}", path.display(), e));
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.map_err(io_error)?;
+        }
+
+        // Write next to the target and rename, so readers never see half a blob
+        let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4().to_simple()));
+        let mut file = fs::File::create(&tmp).await.map_err(io_error)?;
+        file.write_all(data).await.map_err(io_error)?;
+        file.sync_all().await.map_err(io_error)?;
+        drop(file);
+
+        if let Err(e) = fs::rename(&tmp, path).await {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(io_error(e));
+        }
+        Ok(())
+    }
+
+    async fn references(&self, sha256: &str) -> Result<i64, ApiError> {
+        let query = "
+            SELECT
+                (SELECT COUNT(*) FROM files WHERE sha256 = ? AND storage = ?)
+                + (SELECT COUNT(*) FROM authors WHERE photo_sha256 = ? AND photo_storage = ?)
+        ";
+
+        let (count,): (i64,) = sqlx::query_as(query)
+            .bind(sha256)
+            .bind(self.name())
+            .bind(sha256)
+            .bind(self.name())
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    fn name(&self) -> &'static str {
+        "filesystem"
+    }
+
+    fn seeks(&self) -> bool {
+        true
+    }
+
+    async fn put(&self, owner: BlobOwner, sha256: &str, data: &[u8]) -> Result<(), ApiError> {
+        let path = self.path(sha256)?;
+        let _guard = self.lock.lock().await;
+
+        if fs::metadata(&path).await.is_err() {
+            self.write(&path, data).await?;
+        }
+
+        let c = owner.columns();
+        let query = format!("UPDATE {} SET {} = NULL, {} = ?, {} = ? WHERE id = ?", c.table, c.blob, c.sha256, c.storage);
+
+        sqlx::query(&query)
+            .bind(sha256)
+            .bind(self.name())
+            .bind(c.id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn read(&self, _owner: BlobOwner, sha256: &str, offset: u64, length: u64) -> Result<Vec<u8>, ApiError> {
+        let path = self.path(sha256)?;
+        let io_error = |e: std::io::Error| ApiError::Internal(format!("Could not read blob {}: {}", path.display(), e));
+
+        let mut file = fs::File::open(&path).await.map_err(io_error)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(io_error)?;
+
+        let mut data = Vec::new();
+        file.take(length).read_to_end(&mut data).await.map_err(io_error)?;
+        Ok(data)
+    }
+
+    async fn release(&self, sha256: &str) -> Result<(), ApiError> {
+        let path = self.path(sha256)?;
+        let _guard = self.lock.lock().await;
+
+        if self.references(sha256).await? > 0 {
+            return Ok(());
+        }
+
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(ApiError::Internal(format!("Could not remove blob {}: {}", path.display(), e))),
+        }
+    }
+}
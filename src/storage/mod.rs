@@ -0,0 +1,168 @@
+pub mod archive;
+pub mod filesystem;
+pub mod sqlite;
+pub mod thumbnails;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+
+use crate::config::{ScanConfig, StorageBackend, StorageConfig};
+use crate::errors::ApiError;
+use crate::scanner::UploadScanner;
+use filesystem::FsBlobStore;
+use sqlite::SqliteBlobStore;
+
+// The row a blob belongs to. Only the table and column names below ever reach SQL
+#[derive(Debug, Clone, Copy)]
+pub enum BlobOwner {
+    File(i64),
+    AuthorPhoto(i64),
+}
+
+pub(crate) struct BlobColumns {
+    pub table: &'static str,
+    pub blob: &'static str,
+    pub storage: &'static str,
+    pub sha256: &'static str,
+    pub id: i64,
+}
+
+impl BlobOwner {
+    pub(crate) fn columns(&self) -> BlobColumns {
+        match *self {
+            BlobOwner::File(id) => BlobColumns { table: "files", blob: "file", storage: "storage", sha256: "sha256", id },
+            BlobOwner::AuthorPhoto(id) => {
+                BlobColumns { table: "authors", blob: "photo", storage: "photo_storage", sha256: "photo_sha256", id }
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    // Recorded in the owner's storage column, reads are routed by it
+    fn name(&self) -> &'static str;
+
+    // Whether reading part of a blob costs less than reading all of it
+    fn seeks(&self) -> bool;
+
+    // Save the bytes of an existing row and point the row at this store
+    async fn put(&self, owner: BlobOwner, sha256: &str, data: &[u8]) -> Result<(), ApiError>;
+
+    // Up to `length` bytes starting at `offset`
+    async fn read(&self, owner: BlobOwner, sha256: &str, offset: u64, length: u64) -> Result<Vec<u8>, ApiError>;
+
+    // A row that used these bytes was deleted or now points at other bytes
+    async fn release(&self, sha256: &str) -> Result<(), ApiError>;
+}
+
+//ALL BLOB STORES, WITH THE CONFIGURED ONE RECEIVING NEW UPLOADS
+//
+// Rows remember which store holds them, so switching the backend never
+// strands old uploads: they are read from where they were written. The
+// malware scanner comes along, since new bytes go through it before they
+// reach a store
+#[derive(Clone)]
+pub struct Blobs {
+    backend: StorageBackend,
+    sqlite: Arc<SqliteBlobStore>,
+    filesystem: Arc<FsBlobStore>,
+    scanner: UploadScanner,
+}
+
+impl Blobs {
+    pub fn new(db: SqlitePool, config: &StorageConfig, scanning: &ScanConfig) -> Blobs {
+        Blobs {
+            backend: config.backend,
+            sqlite: Arc::new(SqliteBlobStore::new(db.clone())),
+            filesystem: Arc::new(FsBlobStore::new(db, config.path.clone())),
+            scanner: UploadScanner::new(scanning),
+        }
+    }
+
+    pub fn scanner(&self) -> &UploadScanner {
+        &self.scanner
+    }
+
+    pub fn writer(&self) -> &dyn BlobStore {
+        match self.backend {
+            StorageBackend::Sqlite => self.sqlite.as_ref(),
+            StorageBackend::Filesystem => self.filesystem.as_ref(),
+        }
+    }
+
+    pub fn filesystem(&self) -> &FsBlobStore {
+        &self.filesystem
+    }
+
+    // The store named by a row's storage column
+    pub fn store(&self, storage: &str) -> Result<&dyn BlobStore, ApiError> {
+        if storage == self.sqlite.name() {
+            Ok(self.sqlite.as_ref())
+        } else if storage == self.filesystem.name() {
+            Ok(self.filesystem.as_ref())
+        } else {
+            Err(ApiError::Internal(format!("Unknown blob storage {:?}", storage)))
+        }
+    }
+
+    pub async fn read_all(&self, storage: &str, owner: BlobOwner, sha256: &str) -> Result<Vec<u8>, ApiError> {
+        self.store(storage)?.read(owner, sha256, 0, u64::MAX).await
+    }
+
+    pub async fn release(&self, storage: &str, sha256: Option<&str>) -> Result<(), ApiError> {
+        match sha256 {
+            Some(sha256) => self.store(storage)?.release(sha256).await,
+            None => Ok(()),
+        }
+    }
+}
+
+//MOVE EVERY BLOB STILL INSIDE THE DATABASE TO THE FILESYSTEM STORE
+//
+// Run with `rest_api migrate-blobs`. Safe to interrupt and run again, each
+// row is switched over on its own once its bytes are on disk
+pub async fn migrate_to_filesystem(db: &SqlitePool, blobs: &Blobs) -> Result<(usize, usize), ApiError> {
+    let store = blobs.filesystem();
+
+    let mut files = 0;
+    loop {
+        let rows: Vec<(i64, Option<String>, Vec<u8>)> = sqlx::query_as(
+            "SELECT id, sha256, file FROM files WHERE storage = 'sqlite' AND file IS NOT NULL LIMIT 50",
+        )
+        .fetch_all(db)
+        .await?;
+        if rows.is_empty() {
+            break;
+        }
+        for (id, sha256, data) in rows {
+            let sha256 = sha256.unwrap_or_else(|| crate::db::files::sha256_hex(&data));
+            store.put(BlobOwner::File(id), &sha256, &data).await?;
+            files += 1;
+        }
+    }
+
+    let mut photos = 0;
+    loop {
+        let rows: Vec<(i64, Option<String>, Vec<u8>)> = sqlx::query_as(
+            "SELECT id, photo_sha256, photo FROM authors WHERE photo_storage = 'sqlite' AND photo IS NOT NULL LIMIT 50",
+        )
+        .fetch_all(db)
+        .await?;
+        if rows.is_empty() {
+            break;
+        }
+        for (id, sha256, data) in rows {
+            let sha256 = sha256.unwrap_or_else(|| crate::db::files::sha256_hex(&data));
+            store.put(BlobOwner::AuthorPhoto(id), &sha256, &data).await?;
+            photos += 1;
+        }
+    }
+
+    // Give the space back to the filesystem
+    sqlx::query("VACUUM").execute(db).await?;
+
+    Ok((files, photos))
+}
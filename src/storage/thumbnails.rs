@@ -0,0 +1,128 @@
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{ImageFormat, ImageOutputFormat};
+use sqlx::SqlitePool;
+
+use super::{BlobOwner, Blobs};
+use crate::errors::ApiError;
+
+// Largest width or height a thumbnail can be asked for
+pub const MAX_THUMBNAIL_SIZE: u32 = 1024;
+const JPEG_QUALITY: u8 = 85;
+
+// The box a thumbnail has to fit in. A side that is None follows the aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThumbnailSize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ThumbnailSize {
+    // None when neither side is given, the original is wanted then
+    pub fn parse(width: Option<u32>, height: Option<u32>) -> Result<Option<ThumbnailSize>, ApiError> {
+        for side in [width, height].into_iter().flatten() {
+            if side == 0 || side > MAX_THUMBNAIL_SIZE {
+                return Err(ApiError::BadRequest(format!("w and h must be between 1 and {}", MAX_THUMBNAIL_SIZE)));
+            }
+        }
+
+        match (width, height) {
+            (None, None) => Ok(None),
+            (width, height) => Ok(Some(ThumbnailSize { width, height })),
+        }
+    }
+
+    // Part of the ETag, so each variant is cached separately by clients
+    pub fn tag(&self) -> String {
+        format!("{}x{}", self.width.unwrap_or(0), self.height.unwrap_or(0))
+    }
+}
+
+//A RESIZED AUTHOR PHOTO, MADE ON FIRST REQUEST AND KEPT IN author_photo_thumbnails
+pub async fn thumbnail(
+    db: &SqlitePool,
+    blobs: &Blobs,
+    author_id: i64,
+    storage: &str,
+    sha256: &str,
+    size: ThumbnailSize,
+) -> Result<Vec<u8>, ApiError> {
+    let width = size.width.unwrap_or(0) as i64;
+    let height = size.height.unwrap_or(0) as i64;
+
+    let cached: Option<(Vec<u8>,)> =
+        sqlx::query_as("SELECT data FROM author_photo_thumbnails WHERE photo_sha256 = ? AND width = ? AND height = ?")
+            .bind(sha256)
+            .bind(width)
+            .bind(height)
+            .fetch_optional(db)
+            .await?;
+    if let Some((data,)) = cached {
+        return Ok(data);
+    }
+
+    let photo = blobs.read_all(storage, BlobOwner::AuthorPhoto(author_id), sha256).await?;
+    // Decoding and resampling are CPU bound, keep them off the async workers
+    let (photo, resized) = tokio::task::spawn_blocking(move || {
+        let resized = resize(&photo, size);
+        (photo, resized)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("Thumbnail task failed: {}", e)))?;
+
+    // Already small enough, there is nothing worth caching
+    let data = match resized? {
+        Some(data) => data,
+        None => return Ok(photo),
+    };
+
+    // Two requests may race to make the same variant, both results are equal
+    sqlx::query("INSERT OR IGNORE INTO author_photo_thumbnails (photo_sha256, width, height, data) VALUES (?, ?, ?, ?)")
+        .bind(sha256)
+        .bind(width)
+        .bind(height)
+        .bind(&data)
+        .execute(db)
+        .await?;
+
+    Ok(data)
+}
+
+//DROP THE THUMBNAILS OF A PHOTO NO AUTHOR USES ANY MORE
+pub async fn prune(db: &SqlitePool, sha256: &str) -> Result<(), ApiError> {
+    let query = "
+        DELETE FROM author_photo_thumbnails
+        WHERE photo_sha256 = ?
+        AND NOT EXISTS (SELECT 1 FROM authors WHERE photo_sha256 = ?)
+    ";
+
+    sqlx::query(query).bind(sha256).bind(sha256).execute(db).await?;
+    Ok(())
+}
+
+// Scale down to fit the box keeping the aspect ratio, never up. The result
+// keeps the photo's format so the Content-Type stays the same. None when the
+// photo already fits
+fn resize(photo: &[u8], size: ThumbnailSize) -> Result<Option<Vec<u8>>, ApiError> {
+    let undecodable = |e: image::ImageError| ApiError::Internal(format!("Stored photo could not be decoded: {}", e));
+
+    let format = image::guess_format(photo).map_err(undecodable)?;
+    let image = image::load_from_memory_with_format(photo, format).map_err(undecodable)?;
+
+    let width = size.width.unwrap_or(u32::MAX);
+    let height = size.height.unwrap_or(u32::MAX);
+    if image.width() <= width && image.height() <= height {
+        return Ok(None);
+    }
+
+    let thumbnail = image.thumbnail(width, height);
+    let mut data = Vec::new();
+    let encoded = match format {
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode_image(&thumbnail.to_rgb8()),
+        _ => thumbnail.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png),
+    };
+    encoded.map_err(|e| ApiError::Internal(format!("Thumbnail could not be encoded: {}", e)))?;
+
+    Ok(Some(data))
+}
@@ -2,14 +2,19 @@ use std::sync::Arc;
 
 use crate::models::posts::Post;
 use crate::models::authors::Author;
+use crate::models::files::FileMetadata;
 use utoipa::{openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi};
 use utoipa_swagger_ui::Config;
 use crate::models::response::*;
-const DB_URL: &str = "sqlite://sqlite.db";
-use crate::handlers::author_handler::{__path_get_all_authors, __path_get_author, __path_delete_author, __path_update_author, __path_post_author};
-use crate::handlers::posts_handler::{__path_create_post, __path_get_all_posts, __path_delete_post, __path_download_files_by_id, __path_get_post, __path_get_posts_by_auth, __path_update_post};
-use crate::handlers::user_handlers::{__path_register_user_handler, __path_login_user_handler};
+use crate::models::user::Role;
+use crate::errors::ErrorBody;
+use crate::handlers::author_handler::{__path_get_all_authors, __path_get_author, __path_delete_author, __path_update_author, __path_post_author, __path_upload_author_photo};
+use crate::handlers::posts_handler::{__path_create_post, __path_get_all_posts, __path_delete_post, __path_download_files_by_id, __path_get_post, __path_get_posts_by_auth, __path_update_post, __path_upload_post_files, __path_get_post_files};
+use crate::handlers::events_handler::__path_get_events;
+use crate::handlers::files_handler::{__path_get_file, __path_get_author_photo, __path_get_post_files_zip};
+use crate::handlers::uploads_handler::{__path_tus_options, __path_create_tus_upload, __path_head_tus_upload, __path_patch_tus_upload, __path_delete_tus_upload};
+use crate::handlers::user_handlers::{__path_register_user_handler, __path_login_user_handler, __path_refresh_token_handler, __path_logout_handler, __path_update_role_handler, __path_usage_handler, __path_update_quota_handler};
 use warp::{
     http::Uri,
     hyper::{Response, StatusCode},
@@ -20,7 +25,10 @@ use warp::{
         #[openapi(
             paths(get_all_authors, post_author, get_author, delete_author, update_author,
              get_post, update_post, get_posts_by_auth, create_post, delete_post, get_all_posts, download_files_by_id,
-             register_user_handler, login_user_handler
+             upload_post_files, get_post_files, get_post_files_zip, upload_author_photo, get_file, get_author_photo,
+             get_events, tus_options, create_tus_upload, head_tus_upload, patch_tus_upload, delete_tus_upload,
+             register_user_handler, login_user_handler, refresh_token_handler, logout_handler, update_role_handler,
+             usage_handler, update_quota_handler
             ),
             components(
                 schemas(
@@ -32,11 +40,28 @@ use warp::{
                     UpdatePostRequest,
                     AuthorResponse,
                     PostResponse,
+                    PageInfo,
+                    PostSearchHit,
+                    PostSearchResponse,
+                    AuthorSearchHit,
+                    AuthorSearchResponse,
                     SingeAuthorResponse,
                     SingePostResponse,
                     UserRequest,
                     LoginResponse,
-                    FileResponse
+                    RefreshTokenRequest,
+                    UpdateRoleRequest,
+                    UpdateQuotaRequest,
+                    StorageUsage,
+                    UsageResponse,
+                    Role,
+                    ErrorBody,
+                    FileResponse,
+                    UploadResponse,
+                    FileListResponse,
+                    FileMetadata,
+                    UploadFilesForm,
+                    AuthorPhotoForm
                 )
             )
         )]
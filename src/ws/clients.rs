@@ -1,16 +1,284 @@
-
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{Mutex, mpsc};
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+use sqlx::SqlitePool;
+use tokio::sync::{Mutex, MutexGuard, mpsc};
 use warp::ws::Message;
 
+use crate::config::EventsConfig;
+use crate::db::events::{insert_event, replay_events, Replay};
+use crate::errors::ApiError;
+use crate::models::user::Role;
+use crate::ws::events::Event;
+use crate::ws::topics::Topic;
+
+// Where a client's events go, a WebSocket or a text/event-stream response
+#[derive(Debug, Clone)]
+pub enum ClientSender {
+    WebSocket(mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>),
+    // The event's seq with its JSON
+    EventStream(mpsc::UnboundedSender<(i64, String)>),
+}
+
+impl ClientSender {
+    // False once the connection is gone
+    pub fn send_event(&self, seq: i64, message: String) -> bool {
+        match self {
+            ClientSender::WebSocket(sender) => sender.send(Ok(Message::text(message))).is_ok(),
+            ClientSender::EventStream(sender) => sender.send((seq, message)).is_ok(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Client {
     pub client_id: String,
-    pub sender: Option<mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>>,
+    pub sender: Option<ClientSender>,
+    // Who the connection's token belongs to. Only authenticated
+    // connections are ever added to Clients
+    pub username: String,
+    pub role: Role,
+    // Events are only sent for these, a new connection has none
+    pub topics: HashSet<Topic>,
+}
+
+impl Client {
+    pub fn is_subscribed(&self, topics: &[Topic]) -> bool {
+        topics.iter().any(|topic| self.topics.contains(topic))
+    }
+}
+
+//EVERY CONNECTED CLIENT
+#[derive(Debug, Default)]
+pub struct Hub {
+    pub clients: HashMap<String, Client>,
+    // Clients still being sent the events they missed. New events wait here
+    // until those are out
+    catching_up: HashMap<String, Vec<(i64, String)>>,
 }
 
-pub type Clients = Arc<Mutex<HashMap<String, Client>>>;
+impl Hub {
+    //SEND AN EVENT TO EVERY CLIENT SUBSCRIBED TO ONE OF ITS TOPICS
+    //
+    // Each client gets the event as its role may see it, or not at all.
+    // Clients whose connection is gone are dropped
+    fn broadcast(&mut self, event: &Event) {
+        let topics = event.topics();
+        let mut messages: Vec<(Role, Option<String>)> = Vec::new();
+        let mut deliveries = Vec::new();
+
+        for (client_id, client) in self.clients.iter() {
+            if !client.is_subscribed(&topics) {
+                continue;
+            }
+
+            let message = match messages.iter().find(|(role, _)| *role == client.role) {
+                Some((_, message)) => message.clone(),
+                None => {
+                    let message = event.to_json_for(client.role);
+                    messages.push((client.role, message.clone()));
+                    message
+                }
+            };
+            if let Some(message) = message {
+                deliveries.push((client_id.clone(), message));
+            }
+        }
+
+        for (client_id, message) in deliveries {
+            if !self.send_event(&client_id, event.seq, message) {
+                self.clients.remove(&client_id);
+            }
+        }
+    }
+
+    // False once the client's connection is gone
+    fn send_event(&mut self, client_id: &str, seq: i64, message: String) -> bool {
+        if let Some(buffer) = self.catching_up.get_mut(client_id) {
+            buffer.push((seq, message));
+            return true;
+        }
+        match self.clients.get(client_id).and_then(|client| client.sender.as_ref()) {
+            Some(sender) => sender.send_event(seq, message),
+            None => true,
+        }
+    }
+}
+
+//THE CONNECTED CLIENTS, AND WHERE THEIR EVENTS ARE STORED
+//
+// The lock on the Hub is only held to look at or change clients, never over
+// a query. Storing and sending an event happens under a lock of its own, so
+// events reach every client in seq order
+#[derive(Debug, Clone)]
+pub struct Clients {
+    hub: Arc<Mutex<Hub>>,
+    sequence: Arc<Mutex<()>>,
+    db: SqlitePool,
+    max_replay: i64,
+}
+
+impl Clients {
+    pub async fn lock(&self) -> MutexGuard<'_, Hub> {
+        self.hub.lock().await
+    }
+
+    //STORE THE EVENT, WHICH GIVES IT ITS SEQ, THEN SEND IT TO ITS SUBSCRIBERS
+    //
+    // The next event is only stored once this one is sent
+    pub async fn publish(&self, event: &mut Event) -> Result<(), ApiError> {
+        let _sequence = self.sequence.lock().await;
+        event.seq = insert_event(&self.db, event).await?;
+        self.lock().await.broadcast(event);
+        Ok(())
+    }
 
-pub fn create_clients() -> Clients {
-    Arc::new(Mutex::new(HashMap::new()))
-}
\ No newline at end of file
+    //ADD A CLIENT, FIRST SENDING IT THE EVENTS IT MISSED AFTER `since`
+    //
+    // Only those for its topics and as its role may see them. When they can't
+    // all be sent it gets a stream.reset event instead. The client is added
+    // before the missed events are looked up, events sent in the meantime are
+    // held back until they are out and dropped when they were among them
+    pub async fn join(&self, client: Client, since: Option<i64>) -> Result<(), ApiError> {
+        let (since, sender) = match (since, client.sender.clone()) {
+            (Some(since), Some(sender)) => (since, sender),
+            _ => {
+                self.lock().await.clients.insert(client.client_id.clone(), client);
+                return Ok(());
+            }
+        };
+        let client_id = client.client_id.clone();
+        let joined = client.clone();
+        {
+            let mut hub = self.lock().await;
+            hub.catching_up.insert(client_id.clone(), Vec::new());
+            hub.clients.insert(client_id.clone(), client);
+        }
+
+        let replay = match replay_events(&self.db, since, self.max_replay).await {
+            Ok(replay) => replay,
+            Err(e) => {
+                let mut hub = self.lock().await;
+                hub.catching_up.remove(&client_id);
+                hub.clients.remove(&client_id);
+                return Err(e);
+            }
+        };
+        let replayed = match replay {
+            Replay::Events(events) => {
+                let mut replayed = since;
+                for event in events {
+                    replayed = event.seq;
+                    if !joined.is_subscribed(&event.topics()) {
+                        continue;
+                    }
+                    if let Some(message) = event.to_json_for(joined.role) {
+                        sender.send_event(event.seq, message);
+                    }
+                }
+                replayed
+            }
+            Replay::Reset(seq) => {
+                sender.send_event(seq, reset_message(seq));
+                seq
+            }
+        };
+
+        let mut hub = self.lock().await;
+        for (seq, message) in hub.catching_up.remove(&client_id).unwrap_or_default() {
+            if seq > replayed {
+                sender.send_event(seq, message);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn reset_message(seq: i64) -> String {
+    serde_json::json!({
+        "type": "stream.reset",
+        "seq": seq,
+        "message": "Missed events are no longer available, reload and continue from seq",
+    })
+    .to_string()
+}
+
+pub fn create_clients(db: SqlitePool, config: &EventsConfig) -> Clients {
+    Clients {
+        hub: Arc::new(Mutex::new(Hub::default())),
+        sequence: Arc::new(Mutex::new(())),
+        db,
+        max_replay: config.max_replay,
+    }
+}
+
+// Removes a client from Clients when dropped, for connections that have no
+// loop of their own to do it once they end
+pub struct ClientGuard {
+    clients: Clients,
+    client_id: String,
+}
+
+impl ClientGuard {
+    pub fn new(clients: Clients, client_id: String) -> ClientGuard {
+        ClientGuard { clients, client_id }
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        let clients = self.clients.clone();
+        let client_id = std::mem::take(&mut self.client_id);
+        tokio::spawn(async move {
+            clients.lock().await.clients.remove(&client_id);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory_db;
+    use crate::models::authors::Author;
+    use crate::ws::events::EventData;
+
+    fn author_created(id: i64) -> Event {
+        let author = Author {
+            id,
+            name: "Ann".to_string(),
+            surname: "Lee".to_string(),
+            photo_url: None,
+            created_at: "2026-10-18 12:00:00".to_string(),
+            updated_at: "2026-10-18 12:00:00".to_string(),
+            created_by: None,
+            photo_sha256: None,
+        };
+        Event::new(EventData::AuthorCreated(author), "ann")
+    }
+
+    fn client(sender: mpsc::UnboundedSender<(i64, String)>) -> Client {
+        Client {
+            client_id: "c1".to_string(),
+            sender: Some(ClientSender::EventStream(sender)),
+            username: "ann".to_string(),
+            role: Role::Reader,
+            topics: HashSet::from([Topic::Authors]),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_missed_events_before_new_ones() {
+        let clients = create_clients(memory_db().await, &EventsConfig::default());
+        for id in 1..=3 {
+            clients.publish(&mut author_created(id)).await.unwrap();
+        }
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        clients.join(client(sender), Some(1)).await.unwrap();
+        clients.publish(&mut author_created(4)).await.unwrap();
+
+        let mut seqs = Vec::new();
+        while let Ok((seq, _)) = receiver.try_recv() {
+            seqs.push(seq);
+        }
+        assert_eq!(seqs, vec![2, 3, 4]);
+    }
+}
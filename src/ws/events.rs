@@ -0,0 +1,112 @@
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::authors::Author;
+use crate::models::files::FileMetadata;
+use crate::models::posts::Post;
+use crate::models::user::Role;
+use crate::ws::topics::Topic;
+
+// What changed, with the entity as it is now. Deleted entities are sent as
+// they were just before the delete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum EventData {
+    #[serde(rename = "post.created")]
+    PostCreated(Post),
+    #[serde(rename = "post.updated")]
+    PostUpdated(Post),
+    #[serde(rename = "post.deleted")]
+    PostDeleted(Post),
+    // Files added to a post with POST /api/posts/{id}/files
+    #[serde(rename = "post.files_added")]
+    PostFilesAdded { post_id: i64, author_id: i64, files: Vec<FileMetadata> },
+    #[serde(rename = "author.created")]
+    AuthorCreated(Author),
+    #[serde(rename = "author.updated")]
+    AuthorUpdated(Author),
+    #[serde(rename = "author.deleted")]
+    AuthorDeleted(Author),
+}
+
+//AN EVENT AS SENT TO WEBSOCKET AND EVENT STREAM CLIENTS
+//
+// {"type":"post.created","seq":42,"id":7,"data":{...},"actor":"ann","ts":"2026-10-18T14:05:00.000Z"}
+// where id is the id of the post or author the event is about, and seq the
+// event's own number, to reconnect with ?since=
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(flatten)]
+    pub data: EventData,
+    // Assigned once the event is stored, see db::events
+    #[serde(default)]
+    pub seq: i64,
+    pub id: i64,
+    // Username of whoever made the change
+    pub actor: String,
+    pub ts: String,
+}
+
+impl Event {
+    pub fn new(data: EventData, actor: &str) -> Event {
+        let id = match &data {
+            EventData::PostCreated(post) | EventData::PostUpdated(post) | EventData::PostDeleted(post) => post.id,
+            EventData::PostFilesAdded { post_id, .. } => *post_id,
+            EventData::AuthorCreated(author) | EventData::AuthorUpdated(author) | EventData::AuthorDeleted(author) => {
+                author.id
+            }
+        };
+
+        Event {
+            data,
+            seq: 0,
+            id,
+            actor: actor.to_string(),
+            ts: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+    // Subscribers of any of these receive the event
+    pub fn topics(&self) -> Vec<Topic> {
+        match &self.data {
+            EventData::PostCreated(post) | EventData::PostUpdated(post) | EventData::PostDeleted(post) => {
+                vec![Topic::Posts, Topic::Post(post.id), Topic::AuthorPosts(post.author_id)]
+            }
+            EventData::PostFilesAdded { post_id, author_id, .. } => {
+                vec![Topic::Posts, Topic::Post(*post_id), Topic::AuthorPosts(*author_id)]
+            }
+            EventData::AuthorCreated(author) | EventData::AuthorUpdated(author) | EventData::AuthorDeleted(author) => {
+                vec![Topic::Authors, Topic::Author(author.id)]
+            }
+        }
+    }
+
+    // The event as a user with this role may see it, None when they may not
+    // see any of it. Quarantined files are only shown to admins
+    pub fn visible_to(&self, role: Role) -> Option<Event> {
+        match &self.data {
+            EventData::PostFilesAdded { post_id, author_id, files } if role != Role::Admin => {
+                let files: Vec<FileMetadata> = files.iter().filter(|file| !file.is_quarantined()).cloned().collect();
+                if files.is_empty() {
+                    return None;
+                }
+                Some(Event {
+                    data: EventData::PostFilesAdded { post_id: *post_id, author_id: *author_id, files },
+                    ..self.clone()
+                })
+            }
+            _ => Some(self.clone()),
+        }
+    }
+
+    // The JSON sent to a user with this role, None when they may not see it
+    pub fn to_json_for(&self, role: Role) -> Option<String> {
+        let event = self.visible_to(role)?;
+        match serde_json::to_string(&event) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                eprintln!("Could not serialize event: {}", e);
+                None
+            }
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod clients;
+pub mod events;
+pub mod topics;
 pub mod ws_handler;
\ No newline at end of file
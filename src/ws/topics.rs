@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::Role;
+
+// Most topics one connection can hold
+pub const MAX_TOPICS: usize = 100;
+
+//WHAT A CLIENT CAN SUBSCRIBE TO
+//
+// posts            every post event
+// posts:42         events about post 42
+// authors          every author event
+// authors:7        events about author 7
+// author:7:posts   events about posts of author 7
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Posts,
+    Post(i64),
+    Authors,
+    Author(i64),
+    AuthorPosts(i64),
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = value.split(':').collect();
+        let id = |part: &str| part.parse::<i64>().ok().filter(|id| *id > 0);
+
+        let topic = match parts.as_slice() {
+            ["posts"] => Some(Topic::Posts),
+            ["posts", post_id] => id(post_id).map(Topic::Post),
+            ["authors"] => Some(Topic::Authors),
+            ["authors", author_id] => id(author_id).map(Topic::Author),
+            ["author", author_id, "posts"] => id(author_id).map(Topic::AuthorPosts),
+            _ => None,
+        };
+        topic.ok_or_else(|| format!("Unknown topic {:?}", value))
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topic::Posts => write!(f, "posts"),
+            Topic::Post(id) => write!(f, "posts:{}", id),
+            Topic::Authors => write!(f, "authors"),
+            Topic::Author(id) => write!(f, "authors:{}", id),
+            Topic::AuthorPosts(id) => write!(f, "author:{}:posts", id),
+        }
+    }
+}
+
+// What a client sends: {"type":"subscribe","topics":["posts:42"]}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientMessage {
+    // The first message when no token came with the handshake. Sent again
+    // with a fresh token, it keeps the connection open past the old expiry
+    Auth { token: String },
+    Subscribe { topics: Vec<String> },
+    Unsubscribe { topics: Vec<String> },
+}
+
+// The answer to a client message. Events are sent as ws::events::Event
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    // expires_at is a Unix timestamp, the connection is closed then
+    Authenticated { username: String, role: Role, expires_at: usize },
+    // Every topic the connection holds after the change
+    Subscribed { topics: Vec<String> },
+    Error { message: String },
+}
+
+// All of the topics, or an error naming the first one that isn't valid
+pub fn parse_topics(topics: &[String]) -> Result<Vec<Topic>, String> {
+    topics.iter().map(|topic| topic.parse()).collect()
+}
+
+// Comma separated topics as given in a query string: posts,author:7:posts
+pub fn parse_topic_list(topics: &str) -> Result<HashSet<Topic>, String> {
+    let topics: Vec<String> = topics.split(',').map(|topic| topic.trim().to_string()).collect();
+    let topics: HashSet<Topic> = parse_topics(&topics)?.into_iter().collect();
+    if topics.len() > MAX_TOPICS {
+        return Err(format!("A connection can subscribe to at most {} topics", MAX_TOPICS));
+    }
+    Ok(topics)
+}
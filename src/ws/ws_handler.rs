@@ -1,18 +1,68 @@
-use crate::{Client, Clients};
+use crate::{Client, ClientSender, Clients};
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::ws::events::Event;
+use crate::ws::topics::{parse_topic_list, parse_topics, ClientMessage, ServerMessage, Topic, MAX_TOPICS};
+use crate::Middleware::auth::{is_token_revoked, validate_jwt, Claims};
+use futures::stream::SplitStream;
 use futures::{FutureExt, StreamExt};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use uuid::Uuid;
+use warp::http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL};
 use warp::{ws::{Message, WebSocket}, reply::Reply, reject::Rejection};
 
+// Client messages are a few topics, nothing needs more
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+// How long a connection without a token has to send its auth message
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+// How often an open connection's token is checked for revocation
+const REVOCATION_CHECK: Duration = Duration::from_secs(30);
+// Sec-WebSocket-Protocol: bearer, <token>. The server answers with "bearer"
+const BEARER_PROTOCOL: &str = "bearer";
+// Close code for a missing, invalid or expired token, 4000 and up are free
+// for applications
+const CLOSE_UNAUTHORIZED: u16 = 4401;
+// The server could not set the connection up
+const CLOSE_INTERNAL_ERROR: u16 = 1011;
 
+type Sender = mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>;
 
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    pub token: Option<String>,
+    // Comma separated topics to start with, instead of subscribing by message
+    pub topics: Option<String>,
+    // Seq of the last event received before reconnecting
+    pub since: Option<i64>,
+}
+
+// The topics a connection starts with, and the seq to send missed events after
+#[derive(Debug)]
+pub struct Subscription {
+    pub topics: HashSet<Topic>,
+    pub since: Option<i64>,
+}
 
 
 
-pub async fn client_connection(ws: WebSocket, clients: Clients) {
+
+pub async fn client_connection(
+    ws: WebSocket,
+    clients: Clients,
+    db: SqlitePool,
+    config: Arc<Config>,
+    claims: Option<Claims>,
+    subscription: Subscription,
+) {
     println!("establishing client connection... {:?}", ws);
-    
+
     let (client_ws_sender, mut client_ws_rcv) = ws.split();
     let (client_sender, client_rcv) = mpsc::unbounded_channel();
     let client_rcv = UnboundedReceiverStream::new(client_rcv);
@@ -21,41 +71,288 @@ pub async fn client_connection(ws: WebSocket, clients: Clients) {
             println!("error sending websocket msg: {}", e);
         }
     }));
+
+    let claims = match claims {
+        Some(claims) => claims,
+        None => match wait_for_auth(&mut client_ws_rcv, &db, &config).await {
+            Ok(claims) => claims,
+            Err(e) => {
+                close(&client_sender, CLOSE_UNAUTHORIZED, &e.message());
+                return;
+            }
+        },
+    };
+    send_reply(&authenticated(&claims), &client_sender);
+    if !subscription.topics.is_empty() {
+        send_reply(&subscribed(&subscription.topics), &client_sender);
+    }
+
     let uuid = Uuid::new_v4().to_simple().to_string();
     let new_client = Client {
         client_id: uuid.clone(),
-        sender: Some(client_sender),
+        sender: Some(ClientSender::WebSocket(client_sender.clone())),
+        username: claims.sub.clone(),
+        role: claims.role,
+        topics: subscription.topics,
     };
+    // The token in use, replaced by auth messages
+    let mut claims = claims;
+    let mut ended = Box::pin(token_ended(db.clone(), claims.clone()));
 
-    
-    clients.lock().await.insert(uuid.clone(), new_client);
-    while let Some(result) = client_ws_rcv.next().await {
-   
-  
+    // Events missed since the client's last connection come before any new one
+    if let Err(e) = clients.join(new_client, subscription.since).await {
+        close(&client_sender, CLOSE_INTERNAL_ERROR, &e.message());
+        return;
     }
-    clients.lock().await.remove(&uuid);
+    loop {
+        let result = tokio::select! {
+            result = client_ws_rcv.next() => result,
+            reason = &mut ended => {
+                close(&client_sender, CLOSE_UNAUTHORIZED, reason);
+                break;
+            }
+        };
+        let message = match result {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                println!("error receiving websocket msg from {}: {}", uuid, e);
+                break;
+            }
+            None => break,
+        };
+        if message.is_close() {
+            break;
+        }
+        // Pings are answered by warp, pongs need no answer
+        if message.is_ping() || message.is_pong() {
+            continue;
+        }
+
+        let jti = claims.jti.clone();
+        let reply = match message.to_str() {
+            Ok(text) => handle_client_message(text, &uuid, &clients, &db, &config, &mut claims).await,
+            Err(_) => ServerMessage::Error { message: "Messages must be JSON text".to_string() },
+        };
+        send_reply(&reply, &client_sender);
+        // A new token, watched from now on instead of the old one
+        if claims.jti != jti {
+            ended = Box::pin(token_ended(db.clone(), claims.clone()));
+        }
+    }
+    clients.lock().await.clients.remove(&uuid);
     println!("{} disconnected", uuid);
 }
 
+//THE CLAIMS OF THE AUTH MESSAGE A CONNECTION WITHOUT A TOKEN HAS TO SEND FIRST
+async fn wait_for_auth(receiver: &mut SplitStream<WebSocket>, db: &SqlitePool, config: &Config) -> Result<Claims, ApiError> {
+    let message = match tokio::time::timeout(AUTH_TIMEOUT, receiver.next()).await {
+        Ok(Some(Ok(message))) => message,
+        Ok(_) => return Err(ApiError::Unauthorized("Connection closed before authenticating".to_string())),
+        Err(_) => return Err(ApiError::Unauthorized("No auth message received in time".to_string())),
+    };
 
+    let token = match message.to_str().ok().and_then(|text| serde_json::from_str(text).ok()) {
+        Some(ClientMessage::Auth { token }) => token,
+        _ => return Err(ApiError::Unauthorized("The first message must be {\"type\":\"auth\",\"token\":...}".to_string())),
+    };
+    validate_jwt(db, config, &token).await
+}
 
-pub async fn ws_handler(ws: warp::ws::Ws, clients: Clients) -> Result<impl Reply, Rejection> {
-    println!("ws_handler");
-    Ok(ws.on_upgrade(move |socket| client_connection(socket, clients)))
+//APPLY A CLIENT MESSAGE: A NEW TOKEN, OR A CHANGE TO THE CLIENT'S TOPICS
+//
+// All topics of a message are checked before any is applied, so a message
+// with one bad topic changes nothing
+async fn handle_client_message(
+    text: &str,
+    client_id: &str,
+    clients: &Clients,
+    db: &SqlitePool,
+    config: &Config,
+    current: &mut Claims,
+) -> ServerMessage {
+    let message: ClientMessage = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => return ServerMessage::Error { message: format!("Invalid message: {}", e) },
+    };
+
+    let (subscribe, topics) = match message {
+        ClientMessage::Auth { token } => return reauthenticate(&token, client_id, clients, db, config, current).await,
+        ClientMessage::Subscribe { topics } => (true, topics),
+        ClientMessage::Unsubscribe { topics } => (false, topics),
+    };
+    let topics = match parse_topics(&topics) {
+        Ok(topics) => topics,
+        Err(message) => return ServerMessage::Error { message },
+    };
+
+    let mut hub = clients.lock().await;
+    let client = match hub.clients.get_mut(client_id) {
+        Some(client) => client,
+        None => return ServerMessage::Error { message: "Connection is closing".to_string() },
+    };
+
+    if subscribe {
+        let added: HashSet<_> = topics.into_iter().filter(|topic| !client.topics.contains(topic)).collect();
+        if client.topics.len() + added.len() > MAX_TOPICS {
+            return ServerMessage::Error { message: format!("A connection can subscribe to at most {} topics", MAX_TOPICS) };
+        }
+        client.topics.extend(added);
+    } else {
+        for topic in &topics {
+            client.topics.remove(topic);
+        }
+    }
+
+    subscribed(&client.topics)
+}
+
+//TAKE A FRESH TOKEN OF THE SAME USER, WHICH MOVES THE EXPIRY BACK
+//
+// From then on it is the token checked for revocation
+async fn reauthenticate(
+    token: &str,
+    client_id: &str,
+    clients: &Clients,
+    db: &SqlitePool,
+    config: &Config,
+    current: &mut Claims,
+) -> ServerMessage {
+    let claims = match validate_jwt(db, config, token).await {
+        Ok(claims) => claims,
+        Err(e) => return ServerMessage::Error { message: e.message() },
+    };
+
+    let mut hub = clients.lock().await;
+    let client = match hub.clients.get_mut(client_id) {
+        Some(client) => client,
+        None => return ServerMessage::Error { message: "Connection is closing".to_string() },
+    };
+    if claims.sub != client.username {
+        return ServerMessage::Error { message: "The token belongs to another user".to_string() };
+    }
+
+    // A new token also brings the user's current role
+    client.role = claims.role;
+    let reply = authenticated(&claims);
+    *current = claims;
+    reply
 }
 
+// Every topic a connection holds, sorted
+fn subscribed(topics: &HashSet<Topic>) -> ServerMessage {
+    let mut topics: Vec<String> = topics.iter().map(|topic| topic.to_string()).collect();
+    topics.sort();
+    ServerMessage::Subscribed { topics }
+}
 
+fn authenticated(claims: &Claims) -> ServerMessage {
+    ServerMessage::Authenticated { username: claims.sub.clone(), role: claims.role, expires_at: claims.exp }
+}
 
+//RESOLVES ONCE A CONNECTION'S TOKEN EXPIRES OR IS REVOKED, WITH THE REASON
+//
+// Revocation is checked every REVOCATION_CHECK. Ends WebSockets and event
+// streams alike
+pub async fn token_ended(db: SqlitePool, claims: Claims) -> &'static str {
+    let expiry = tokio::time::sleep_until(deadline(claims.exp));
+    tokio::pin!(expiry);
+    let mut revocation_check = tokio::time::interval_at(Instant::now() + REVOCATION_CHECK, REVOCATION_CHECK);
 
-pub async fn send_message_to_clients(msg: String, clients: &Clients) {
+    loop {
+        tokio::select! {
+            _ = &mut expiry => return "Token has expired",
+            _ = revocation_check.tick() => match is_token_revoked(&db, &claims).await {
+                Ok(true) => return "Token has been revoked",
+                Ok(false) => {}
+                // Checked again on the next tick
+                Err(e) => eprintln!("Could not check the token of {} for revocation: {:?}", claims.sub, e),
+            },
+        }
+    }
+}
 
-    
+// When a token with this exp (Unix seconds) runs out
+pub fn deadline(expires_at: usize) -> Instant {
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    Instant::now() + Duration::from_secs((expires_at as u64).saturating_sub(now))
+}
 
-    let clients = clients.lock().await;
-    for (_, client) in clients.iter() {
-        if let Some(sender) = &client.sender {
-            println!("sending veikia");
-            let _ = sender.send(Ok(Message::text(msg.to_string())));
+fn send_reply(reply: &ServerMessage, sender: &Sender) {
+    match serde_json::to_string(reply) {
+        Ok(reply) => {
+            let _ = sender.send(Ok(Message::text(reply)));
         }
+        Err(e) => eprintln!("Could not serialize websocket reply: {}", e),
     }
-}
\ No newline at end of file
+}
+
+fn close(sender: &Sender, code: u16, reason: &str) {
+    let _ = sender.send(Ok(Message::close_with(code, reason.to_string())));
+}
+
+// The token from "Sec-WebSocket-Protocol: bearer, <token>", browsers can't
+// set an Authorization header on a WebSocket
+fn token_from_protocols(protocols: Option<&str>) -> Option<String> {
+    let mut protocols = protocols?.split(',').map(str::trim);
+    match (protocols.next(), protocols.next()) {
+        (Some(BEARER_PROTOCOL), Some(token)) if !token.is_empty() => Some(token.to_string()),
+        _ => None,
+    }
+}
+
+
+
+//UPGRADE TO A WEBSOCKET FOR AN AUTHENTICATED USER
+//
+// The token comes from Sec-WebSocket-Protocol, ?token= or the first message.
+// One given with the handshake has to be valid or the upgrade is refused.
+// Browsers always send an Origin, the route's CORS filter only lets
+// server.cors_origins through.
+// A client reconnecting with ?topics= and ?since= first gets what it missed
+pub async fn ws_handler(
+    ws: warp::ws::Ws,
+    protocols: Option<String>,
+    query: WsQuery,
+    clients: Clients,
+    db: SqlitePool,
+    config: Arc<Config>,
+) -> Result<impl Reply, Rejection> {
+    println!("ws_handler");
+    let from_protocol = token_from_protocols(protocols.as_deref());
+    let echo_protocol = from_protocol.is_some();
+
+    let topics = match &query.topics {
+        Some(topics) => parse_topic_list(topics).map_err(ApiError::BadRequest)?,
+        None => HashSet::new(),
+    };
+    if query.since.is_some() && topics.is_empty() {
+        return Err(ApiError::BadRequest("since needs the topics to send missed events for, as ?topics=".to_string()).into());
+    }
+    let subscription = Subscription { topics, since: query.since };
+
+    let claims = match from_protocol.or(query.token) {
+        Some(token) => Some(validate_jwt(&db, &config, &token).await?),
+        None => None,
+    };
+
+    let ws = ws.max_message_size(MAX_MESSAGE_SIZE);
+    let mut response = ws.on_upgrade(move |socket| client_connection(socket, clients, db, config, claims, subscription)).into_response();
+    // Browsers drop the connection unless one of their protocols is accepted
+    if echo_protocol {
+        response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(BEARER_PROTOCOL));
+    }
+    Ok(response)
+}
+
+
+
+
+//SEND AN EVENT AS JSON TO EVERY CLIENT SUBSCRIBED TO ONE OF ITS TOPICS
+//
+// WebSocket and event stream clients alike. The event is stored first, an
+// event that can't be stored isn't sent. See Clients::publish
+pub async fn send_event_to_clients(mut event: Event, clients: &Clients) {
+    if let Err(e) = clients.publish(&mut event).await {
+        eprintln!("Could not store event: {:?}", e);
+    }
+}